@@ -0,0 +1,207 @@
+use super::*;
+
+/// A computer controlled player. It sits and orders while unemployed and cooks once hired,
+/// talking to the model through the same messages a real client would send
+pub struct Bot {
+    player_id: Id,
+    name: String,
+    /// Time until the next order while sitting
+    order_timer: f32,
+    intent_cooldown: IntentCooldown,
+    t: f32,
+}
+
+impl Bot {
+    const NAMES: [&'static str; 8] = [
+        "Mario", "Luigi", "Tony", "Gina", "Paolo", "Rosa", "Enzo", "Chiara",
+    ];
+
+    #[must_use]
+    pub fn spawn(model: &mut Model) -> (Self, Vec<Event>) {
+        let (player_id, events) = model.spawn_player(true);
+        let bot = Self {
+            player_id,
+            name: Self::NAMES.choose(&mut global_rng()).unwrap().to_string(),
+            order_timer: Self::order_delay(),
+            intent_cooldown: default(),
+            t: 0.0,
+        };
+        (bot, events)
+    }
+
+    pub fn player_id(&self) -> Id {
+        self.player_id
+    }
+
+    fn order_delay() -> f32 {
+        global_rng().gen_range(2.0..=8.0)
+    }
+
+    #[must_use]
+    pub fn update(&mut self, model: &Model, delta_time: f32) -> Vec<ClientMessage> {
+        self.t += delta_time;
+        let mut player = match model.players.get(&self.player_id) {
+            Some(player) => player.clone(),
+            None => return vec![],
+        };
+        player.name = self.name.clone();
+        player.target_velocity = vec2(0.0, 0.0);
+        let mut messages = Vec::new();
+        if let Some(time) = &mut player.unemployed_time {
+            *time += delta_time;
+        }
+        match player.unemployed_time {
+            Some(_) => self.update_customer(model, &mut player, delta_time, &mut messages),
+            None => {
+                if let Some(seat_index) = player.seat.take() {
+                    player.position = model.level.seats[seat_index].leave_position;
+                }
+                self.update_cook(model, &mut player, &mut messages);
+            }
+        }
+        if player.seat.is_none() {
+            player.update(&model.config, delta_time);
+            for table in &model.level.tables {
+                player.collide(table.position, table.radius);
+            }
+            for thing in &model.level.kitchen {
+                player.collide(thing.position, thing.radius);
+            }
+        }
+        messages.insert(0, ClientMessage::Event(Event::PlayerUpdated(player)));
+        messages
+    }
+
+    fn update_customer(
+        &mut self,
+        model: &Model,
+        player: &mut Player,
+        delta_time: f32,
+        messages: &mut Vec<ClientMessage>,
+    ) {
+        match player.seat {
+            Some(seat_index) => {
+                player.velocity = vec2(0.0, 0.0);
+                player.position = model.level.seats[seat_index].position;
+                if model.orders[seat_index].is_some() {
+                    return;
+                }
+                self.order_timer -= delta_time;
+                if self.order_timer <= 0.0 {
+                    self.order_timer = Self::order_delay();
+                    let mut order = Order::new();
+                    while order.is_empty() {
+                        for ingredient in Ingredient::all() {
+                            if global_rng().gen_bool(0.5) {
+                                order.insert(ingredient);
+                            }
+                        }
+                    }
+                    messages.push(ClientMessage::Event(Event::Order(seat_index, Some(order))));
+                }
+            }
+            None => {
+                let free_seat = model
+                    .level
+                    .seats
+                    .iter()
+                    .enumerate()
+                    .filter(|&(seat_index, _)| {
+                        !model
+                            .players
+                            .values()
+                            .any(|other| other.seat == Some(seat_index))
+                    })
+                    .min_by_key(|(_, seat)| r32((seat.position - player.position).len()));
+                if let Some((seat_index, seat)) = free_seat {
+                    if player.touches(seat.position, seat.radius) {
+                        player.seat = Some(seat_index);
+                        player.position = seat.position;
+                        player.velocity = vec2(0.0, 0.0);
+                    } else {
+                        Self::walk_to(model, player, seat.position);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks whatever brings the pizza in hands closer to some order
+    fn cook_target(model: &Model, player: &Player) -> Option<(Vec2<f32>, f32, Intent)> {
+        let kitchen_thing = |typ: KitchenThingType| {
+            model
+                .level
+                .kitchen
+                .iter()
+                .position(|thing| thing.typ == typ)
+                .map(|index| {
+                    let thing = &model.level.kitchen[index];
+                    (thing.position, thing.radius, Intent::Interact(index))
+                })
+        };
+        let mut orders = model
+            .orders
+            .iter()
+            .enumerate()
+            .filter_map(|(seat_index, order)| order.as_ref().map(|order| (seat_index, order)));
+        match &player.pizza {
+            None => {
+                if model.orders.iter().any(|order| order.is_some()) {
+                    kitchen_thing(KitchenThingType::Dough)
+                } else {
+                    None
+                }
+            }
+            Some(pizza) if pizza.state == PizzaState::Raw => {
+                match orders.find(|(_, order)| pizza.ingredients.is_subset(order)) {
+                    Some((_, order)) => match order.difference(&pizza.ingredients).next() {
+                        Some(&ingredient) => {
+                            kitchen_thing(KitchenThingType::IngredientBox(ingredient))
+                        }
+                        None => kitchen_thing(KitchenThingType::Oven),
+                    },
+                    None => kitchen_thing(KitchenThingType::TrashCan),
+                }
+            }
+            Some(pizza) => match orders.find(|(_, order)| **order == pizza.ingredients) {
+                Some((seat_index, _)) => {
+                    let seat = &model.level.seats[seat_index];
+                    Some((seat.position, seat.radius, Intent::Deliver(seat_index)))
+                }
+                None => kitchen_thing(KitchenThingType::TrashCan),
+            },
+        }
+    }
+
+    fn update_cook(
+        &mut self,
+        model: &Model,
+        player: &mut Player,
+        messages: &mut Vec<ClientMessage>,
+    ) {
+        if let Some((position, radius, intent)) = Self::cook_target(model, player) {
+            if player.touches(position, radius) {
+                if self.intent_cooldown.try_send(intent, self.t) {
+                    messages.push(ClientMessage::Intent(intent));
+                }
+            } else {
+                Self::walk_to(model, player, position);
+            }
+        }
+    }
+
+    /// Follows the pathfinding graph until the closest node, then goes straight for the target
+    fn walk_to(model: &Model, player: &mut Player, target: Vec2<f32>) {
+        let from = model.find_node(player.position);
+        let to = model.find_node(target);
+        let waypoint = if from == to {
+            target
+        } else {
+            model.level.pathfind_nodes[model.next_node_towards(from, to)]
+        };
+        let direction = waypoint - player.position;
+        if direction.len() > 0.01 {
+            player.target_velocity = direction.normalize();
+        }
+    }
+}
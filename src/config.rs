@@ -0,0 +1,110 @@
+use super::*;
+
+/// Gameplay tuning, part of the [Model] so clients always play by the server's rules
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub ticks_per_second: f64,
+    pub max_employees: usize,
+    /// Seconds between the boss firing the worst cook
+    pub fire_timer: f32,
+    pub boss_walk_speed: f32,
+    pub boss_run_speed: f32,
+    pub player_speed: f32,
+    pub player_acceleration: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ticks_per_second: 20.0,
+            max_employees: 5,
+            fire_timer: 30.0,
+            boss_walk_speed: 4.0,
+            boss_run_speed: 10.0,
+            player_speed: 6.0,
+            player_acceleration: 50.0,
+        }
+    }
+}
+
+impl Config {
+    /// Outside of this the tick loop would either stop or never sleep
+    pub const TICKS_PER_SECOND: std::ops::RangeInclusive<f64> = 1.0..=200.0;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !Self::TICKS_PER_SECOND.contains(&self.ticks_per_second) {
+            return Err(format!(
+                "ticks_per_second has to be between {} and {}, got {}",
+                Self::TICKS_PER_SECOND.start(),
+                Self::TICKS_PER_SECOND.end(),
+                self.ticks_per_second,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Loaded by `--server` from the RON file given with `--config`, missing fields take defaults
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Serves `wss://` when [ServerConfig::tls] is set, plain `ws://` otherwise
+    pub addr: String,
+    /// Bots fill a room up to this many players while there is anyone to play with
+    pub min_players: usize,
+    /// Text file with one banned name per line, kept up to date by the admin console.
+    /// Relative to the config file, or to where the server was started without one.
+    /// `None` keeps bans in memory only
+    pub ban_list: Option<std::path::PathBuf>,
+    /// Relative paths work the same way as for [ServerConfig::ban_list]
+    pub tls: Option<TlsConfig>,
+    pub game: Config,
+}
+
+/// PEM files, the certificate file may hold the whole chain
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub cert: std::path::PathBuf,
+    pub key: std::path::PathBuf,
+}
+
+impl TlsConfig {
+    fn resolve(&mut self, dir: &std::path::Path) {
+        self.cert = dir.join(&self.cert);
+        self.key = dir.join(&self.key);
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: default_addr().to_owned(),
+            min_players: 4,
+            ban_list: Some("bans.txt".into()),
+            tls: None,
+            game: default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)?;
+        let mut config: Self = ron::de::from_reader(file)?;
+        config.game.validate()?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+    /// Makes relative file paths relative to `dir`
+    pub fn resolve_paths(&mut self, dir: &std::path::Path) {
+        if let Some(ban_list) = &mut self.ban_list {
+            *ban_list = dir.join(&*ban_list);
+        }
+        if let Some(tls) = &mut self.tls {
+            tls.resolve(dir);
+        }
+    }
+}
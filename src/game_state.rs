@@ -12,8 +12,10 @@ impl PlayerState {
             position: vec2(0.0, 0.0),
         }
     }
-    pub fn update(&mut self, player: &Player, delta_time: f32) {
-        self.position += (player.position - self.position) * (delta_time * 5.0).min(1.0);
+    /// `latency` is the round trip time, the player has moved on by half of it since the server saw them
+    pub fn update(&mut self, player: &Player, delta_time: f32, latency: f32) {
+        let target = player.position + player.velocity * latency / 2.0;
+        self.position += (target - self.position) * (delta_time * 5.0).min(1.0);
     }
 }
 
@@ -32,6 +34,7 @@ struct Button {
 impl Model {
     fn buttons_for(&self, seat: &Seat) -> Vec<Button> {
         let table = self
+            .level
             .tables
             .iter()
             .min_by_key(|table| r32((table.position - seat.position).len()))
@@ -87,12 +90,27 @@ pub struct GameState {
     show_names: bool,
     text: &'static str,
     text_timer: f32,
+    intent_cooldown: IntentCooldown,
+    room: String,
+    /// Kept to recreate the room the same way if it's gone by the time we reconnect
+    public: bool,
+    token: Option<SessionToken>,
+    last_message_time: f32,
+    reconnecting: Option<(f32, ConnectionFuture)>,
+    timers: ClientTimers,
+    event_sequence: EventSequence,
+    /// Round trip time to the server in seconds
+    latency: Option<f32>,
+    /// Message from the server admin and when to stop showing it
+    announcement: Option<(String, f32)>,
+    /// Who left the game and when to stop showing it
+    notification: Option<(String, f32)>,
 }
 
 impl Drop for GameState {
     fn drop(&mut self) {
         if let Connection::Remote(connection) = &mut self.connection {
-            connection.send(ClientMessage::Event(Event::PlayerLeft(self.player.id)));
+            connection.send(ClientMessage::Leave);
         }
     }
 }
@@ -100,12 +118,22 @@ impl Drop for GameState {
 type RenderQ<'a> = BTreeMap<R32, Vec<Box<dyn Fn(&mut ugli::Framebuffer) + 'a>>>;
 
 impl GameState {
+    const ANNOUNCEMENT_TIME: f32 = 5.0;
+    const NOTIFICATION_TIME: f32 = 3.0;
+    /// The server sends updates every tick, so silence this long means the connection is dead
+    const CONNECTION_TIMEOUT: f32 = 5.0;
+    const RECONNECT_INTERVAL: f32 = 5.0;
+    /// Matches the server's grace period, after that our player is gone anyway
+    const GIVE_UP_TIMEOUT: f32 = 60.0;
+
     pub fn new(
         geng: &Geng,
         assets: &Rc<Assets>,
         opt: &Rc<Opt>,
         name: &str,
         color: Color<f32>,
+        room: String,
+        public: bool,
         welcome: WelcomeMessage,
         connection: Connection,
     ) -> Self {
@@ -135,9 +163,160 @@ impl GameState {
             to_send: Vec::new(),
             framebuffer_size: vec2(1.0, 1.0),
             show_names: true,
+            intent_cooldown: default(),
+            room,
+            public,
+            token: welcome.token,
+            last_message_time: 0.0,
+            reconnecting: None,
+            timers: default(),
+            event_sequence: EventSequence::new(welcome.next_event),
+            latency: None,
+            announcement: None,
+            notification: None,
         }
     }
 
+    /// Offline game where the player starts as a cook, with bots to serve and to help out
+    pub fn practice(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        opt: &Rc<Opt>,
+        name: &str,
+        color: Color<f32>,
+    ) -> Self {
+        const CUSTOMERS: usize = 8;
+        const COOKS: usize = 2;
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        model.handle(Event::Hire(player_id));
+        let mut bots = Vec::new();
+        for i in 0..CUSTOMERS + COOKS {
+            let (bot, _) = Bot::spawn(&mut model);
+            if i < COOKS {
+                model.handle(Event::Hire(bot.player_id()));
+            }
+            bots.push(bot);
+        }
+        let welcome = model.welcome_back(player_id);
+        Self::new(
+            geng,
+            assets,
+            opt,
+            name,
+            color,
+            String::new(),
+            false,
+            welcome,
+            Connection::Local {
+                next_tick: 0.0,
+                model,
+                bots,
+                next_event: 0,
+            },
+        )
+    }
+
+    fn update_reconnect(&mut self) {
+        if self.connection.is_local() || self.token.is_none() {
+            return;
+        }
+        if let Some((started, reconnecting)) = &mut self.reconnecting {
+            if let std::task::Poll::Ready(result) =
+                reconnecting
+                    .as_mut()
+                    .poll(&mut std::task::Context::from_waker(
+                        futures::task::noop_waker_ref(),
+                    ))
+            {
+                self.reconnecting = None;
+                match result {
+                    Ok((welcome, connection)) => self.reconnected(welcome, connection),
+                    Err(error @ ConnectionError::VersionMismatch { .. })
+                    | Err(error @ ConnectionError::Kicked(_)) => self.fail(error),
+                    Err(_) => {}
+                }
+                return;
+            }
+            if *started > self.t - Self::RECONNECT_INTERVAL {
+                return;
+            }
+            self.reconnecting = None;
+        }
+        if self.last_message_time < self.t - Self::GIVE_UP_TIMEOUT {
+            self.fail(ConnectionError::Lost);
+        } else if self.last_message_time < self.t - Self::CONNECTION_TIMEOUT {
+            self.reconnecting = Some((
+                self.t,
+                connect(
+                    &self.opt,
+                    self.room.clone(),
+                    self.public,
+                    self.token,
+                    self.player.name.clone(),
+                ),
+            ));
+        }
+    }
+
+    fn fail(&mut self, error: ConnectionError) {
+        self.reconnecting = None;
+        self.transition = Some(geng::Transition::Switch(Box::new(ConnectingState::failed(
+            &self.geng,
+            &self.assets,
+            &self.opt,
+            self.player.name.clone(),
+            self.player.color,
+            self.room.clone(),
+            self.public,
+            error,
+        ))));
+    }
+
+    fn back_to_menu(&mut self) {
+        self.transition = Some(geng::Transition::Switch(Box::new(Menu::new(
+            &self.geng,
+            &self.assets,
+            &self.opt,
+        ))));
+    }
+
+    /// The server is right about everything except where we are going
+    fn apply_snapshot(&mut self, mut model: Model) {
+        model.level = self.model.level.clone();
+        model.load_level();
+        if let Some(player) = model.players.get(&self.player.id) {
+            self.player.pizza = player.pizza.clone();
+            self.player.score = player.score;
+            if player.unemployed_time.is_some() != self.player.unemployed_time.is_some() {
+                self.player.unemployed_time = player.unemployed_time;
+            }
+            // The server turned down a seat or put us in one, otherwise we keep our own position
+            if player.seat != self.player.seat {
+                self.player.seat = player.seat;
+                self.player.position = player.position;
+                self.player.velocity = vec2(0.0, 0.0);
+            }
+        }
+        self.players.retain(|id, _| model.players.contains_key(id));
+        self.model = model;
+    }
+
+    fn reconnected(&mut self, welcome: WelcomeMessage, connection: Connection) {
+        // If the grace period ran out we get a new player, but keep the looks
+        let mut player = welcome.model.players[&welcome.player_id].clone();
+        player.name = self.player.name.clone();
+        player.color = self.player.color;
+        self.player = player;
+        self.token = welcome.token;
+        self.event_sequence = EventSequence::new(welcome.next_event);
+        self.model = welcome.model;
+        self.connection = connection;
+        self.last_message_time = self.t;
+        self.text = "Reconnected!";
+        self.text_timer = 0.0;
+    }
+
     fn draw_player<'a, 'b>(&'a self, renderq: &'b mut RenderQ<'a>, player: &'a Player) {
         let player_position = if player.id == self.player.id {
             player.position
@@ -161,7 +340,7 @@ impl GameState {
         );
         let mut left = player.left;
         if let Some(seat) = player.seat {
-            let seat = &self.model.seats[seat];
+            let seat = &self.model.level.seats[seat];
             left = seat.leave_position.x > seat.position.x;
             aabb = aabb.translate(vec2(0.0, player.radius));
         }
@@ -311,7 +490,7 @@ impl GameState {
             }
         }
 
-        for seat in &self.model.seats {
+        for seat in &self.model.level.seats {
             self.geng.draw_2d().ellipse(
                 framebuffer,
                 &self.camera,
@@ -336,7 +515,7 @@ impl GameState {
                 }));
         }
 
-        for table in &self.model.tables {
+        for table in &self.model.level.tables {
             self.geng.draw_2d().ellipse(
                 framebuffer,
                 &self.camera,
@@ -361,7 +540,7 @@ impl GameState {
                 }));
         }
 
-        for thing in &self.model.kitchen {
+        for thing in &self.model.level.kitchen {
             self.geng.draw_2d().ellipse(
                 framebuffer,
                 &self.camera,
@@ -483,8 +662,7 @@ impl GameState {
                         + vec2(
                             0.0,
                             if let Some(seat) = player.seat {
-                                let seat = &self.model.seats[seat];
-                                if seat.order.is_some() {
+                                if self.model.orders[seat].is_some() {
                                     player.radius * 2.7
                                 } else {
                                     player.radius * 2.1
@@ -501,11 +679,19 @@ impl GameState {
                     self.assets.font.draw_aligned(
                         framebuffer,
                         &geng::PixelPerfectCamera,
-                        &player.name,
+                        &if player.bot {
+                            format!("{} [bot]", player.name)
+                        } else {
+                            player.name.clone()
+                        },
                         pos,
                         0.5,
                         20.0,
-                        Color::rgba(0.0, 0.0, 0.0, 0.5),
+                        if player.bot {
+                            Color::rgba(0.0, 0.0, 0.5, 0.5)
+                        } else {
+                            Color::rgba(0.0, 0.0, 0.0, 0.5)
+                        },
                     );
                 }
             }
@@ -525,8 +711,8 @@ impl GameState {
             );
         }
 
-        for seat in &self.model.seats {
-            if let Some(order) = &seat.order {
+        for (seat, order) in self.model.level.seats.iter().zip(&self.model.orders) {
+            if let Some(order) = order {
                 self.draw_ingredients(framebuffer, order, seat.position + vec2(0.0, 1.0));
             }
         }
@@ -549,8 +735,8 @@ impl GameState {
         }
 
         if let Some(seat_index) = self.player.seat {
-            let seat = &self.model.seats[seat_index];
-            if seat.order.is_none() {
+            let seat = &self.model.level.seats[seat_index];
+            if self.model.orders[seat_index].is_none() {
                 for button in self.model.buttons_for(seat) {
                     match button.typ {
                         ButtonType::MakeOrder => {
@@ -589,7 +775,26 @@ impl GameState {
             }
         }
 
-        if self.text_timer < 1.0 {
+        if self.reconnecting.is_some() {
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                "Connection lost, reconnecting...",
+                self.framebuffer_size / 2.0,
+                0.5,
+                64.0,
+                Color::BLACK,
+            );
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                "Press Escape to go back to the menu",
+                self.framebuffer_size / 2.0 - vec2(0.0, 60.0),
+                0.5,
+                32.0,
+                Color::GRAY,
+            );
+        } else if self.text_timer < 1.0 {
             self.assets.font.draw_aligned(
                 framebuffer,
                 &geng::PixelPerfectCamera,
@@ -601,19 +806,60 @@ impl GameState {
             );
         }
 
+        if let Some((text, _)) = &self.announcement {
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                text,
+                vec2(
+                    self.framebuffer_size.x / 2.0,
+                    self.framebuffer_size.y - 60.0,
+                ),
+                0.5,
+                40.0,
+                Color::RED,
+            );
+        }
+
+        if let Some((text, _)) = &self.notification {
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                text,
+                vec2(
+                    self.framebuffer_size.x / 2.0,
+                    self.framebuffer_size.y - 100.0,
+                ),
+                0.5,
+                32.0,
+                Color::GRAY,
+            );
+        }
+
+        if let Some(latency) = self.latency {
+            self.assets.font.draw_aligned(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &format!("Ping: {:.0}ms", latency * 1000.0),
+                vec2(self.framebuffer_size.x - 10.0, 10.0),
+                1.0,
+                24.0,
+                Color::GRAY,
+            );
+        }
         self.assets.font.draw(
             framebuffer,
             &geng::PixelPerfectCamera,
             &format!(
                 "Next firing: {:.0}s",
-                (Boss::FIRE_TIMER - self.model.boss.timer).max(0.0)
+                (self.model.config.fire_timer - self.model.boss.timer).max(0.0)
             ),
             vec2(10.0, 10.0),
             48.0,
             Color::BLACK,
         );
 
-        // for &node in &self.model.pathfind_nodes {
+        // for &node in &self.model.level.pathfind_nodes {
         //     self.geng
         //         .draw_2d()
         //         .circle(framebuffer, &self.camera, node, 0.1, Color::GRAY);
@@ -627,7 +873,7 @@ impl GameState {
         match self.player.seat {
             Some(seat_index) => {
                 self.player.velocity = vec2(0.0, 0.0);
-                self.player.position = self.model.seats[seat_index].position;
+                self.player.position = self.model.level.seats[seat_index].position;
             }
             None => {
                 if self.geng.window().is_key_pressed(geng::Key::A)
@@ -653,8 +899,9 @@ impl GameState {
                 if self.player.target_velocity.len() > 0.1 {
                     self.player.target_velocity = self.player.target_velocity.normalize();
                 }
-                self.player.update(delta_time);
-                for (seat_index, seat) in self.model.seats.iter().enumerate() {
+                self.player.update(&self.model.config, delta_time);
+                let mut intents = Vec::new();
+                for (seat_index, seat) in self.model.level.seats.iter().enumerate() {
                     if self.player.collide(seat.position, seat.radius) {
                         match self.player.unemployed_time {
                             Some(_) => {
@@ -669,25 +916,17 @@ impl GameState {
                                 }
                             }
                             None => {
-                                if let Some(order) = &seat.order {
-                                    if let Some(pizza) = &self.player.pizza {
-                                        if pizza.state == PizzaState::Cooked {
-                                            if order == &pizza.ingredients {
-                                                self.player.pizza = None;
-                                                self.player.score += 1;
-                                                self.to_send.push(ClientMessage::Event(
-                                                    Event::Order(seat_index, None),
-                                                ));
-                                                self.to_send.push(ClientMessage::Event(Event::Eat));
-                                            }
-                                        }
-                                    }
+                                if self
+                                    .player
+                                    .can_deliver(self.model.orders[seat_index].as_ref())
+                                {
+                                    intents.push(Intent::Deliver(seat_index));
                                 }
                             }
                         }
                     }
                 }
-                for table in &self.model.tables {
+                for table in &self.model.level.tables {
                     self.player.collide(table.position, table.radius);
                 }
                 // for other_player in self.model.players.values() {
@@ -697,56 +936,24 @@ impl GameState {
                 //     self.player
                 //         .collide(other_player.position, other_player.radius);
                 // }
-                for thing in &self.model.kitchen {
-                    if self.player.collide(thing.position, thing.radius) {
-                        match thing.typ {
-                            KitchenThingType::Dough => {
-                                if self.player.pizza.is_none()
-                                    && self.player.unemployed_time.is_none()
-                                {
-                                    self.player.pizza = Some(Pizza {
-                                        ingredients: BTreeSet::new(),
-                                        state: PizzaState::Raw,
-                                    });
-                                    self.to_send
-                                        .push(ClientMessage::Event(Event::Interacted(thing.typ)));
-                                }
-                            }
-                            KitchenThingType::IngredientBox(ingredient) => {
-                                if let Some(pizza) = &mut self.player.pizza {
-                                    if pizza.state == PizzaState::Raw {
-                                        if pizza.ingredients.insert(ingredient) {
-                                            self.to_send.push(ClientMessage::Event(
-                                                Event::Interacted(thing.typ),
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
-                            KitchenThingType::Oven => {
-                                if let Some(pizza) = &mut self.player.pizza {
-                                    if pizza.state == PizzaState::Raw {
-                                        pizza.state = PizzaState::Cooked;
-                                        self.to_send.push(ClientMessage::Event(Event::Interacted(
-                                            thing.typ,
-                                        )));
-                                    }
-                                }
-                            }
-                            KitchenThingType::TrashCan => {
-                                if self.player.pizza.is_some() {
-                                    self.to_send
-                                        .push(ClientMessage::Event(Event::Interacted(thing.typ)));
-                                    self.player.pizza = None;
-                                }
-                            }
-                        }
+                for (thing_index, thing) in self.model.level.kitchen.iter().enumerate() {
+                    if self.player.collide(thing.position, thing.radius)
+                        && self.player.interaction(thing.typ).is_some()
+                    {
+                        intents.push(Intent::Interact(thing_index));
                     }
                 }
+                for intent in intents {
+                    self.send_intent(intent);
+                }
             }
         }
-        if self.player.unemployed_time.is_some() {
-            self.player.pizza = None;
+    }
+
+    /// Intents are resolved by the server, so don't spam it while waiting for the result
+    fn send_intent(&mut self, intent: Intent) {
+        if self.intent_cooldown.try_send(intent, self.t) {
+            self.to_send.push(ClientMessage::Intent(intent));
         }
     }
 
@@ -799,7 +1006,7 @@ impl GameState {
         let mut target_camera_position = camera.center;
         let mut target_camera_fov = 20.0;
         if let Some(seat_index) = self.player.seat {
-            if self.model.seats[seat_index].order.is_none() {
+            if self.model.orders[seat_index].is_none() {
                 target_camera_fov = 10.0;
             }
         }
@@ -852,31 +1059,67 @@ impl geng::State for GameState {
     fn update(&mut self, delta_time: f64) {
         self.t += delta_time as f32;
         self.text_timer += delta_time as f32;
+        if let Some((_, until)) = self.announcement {
+            if until < self.t {
+                self.announcement = None;
+            }
+        }
+        if let Some((_, until)) = self.notification {
+            if until < self.t {
+                self.notification = None;
+            }
+        }
         let mut messages = Vec::new();
         match &mut self.connection {
             Connection::Remote(connection) => messages.extend(connection.new_messages()),
-            Connection::Local { next_tick, model } => {
+            Connection::Local {
+                next_tick,
+                model,
+                bots,
+                next_event,
+            } => {
                 *next_tick -= delta_time;
                 while *next_tick <= 0.0 {
-                    messages.push(ServerMessage::Update(model.tick()));
-                    *next_tick += 1.0 / model.ticks_per_second;
+                    messages.push(ServerMessage::local_update(next_event, model.tick()));
+                    *next_tick += 1.0 / model.config.ticks_per_second;
+                }
+                for bot in bots {
+                    for message in bot.update(model, delta_time as f32) {
+                        messages.push(ServerMessage::local_update(
+                            next_event,
+                            model.handle_message(bot.player_id(), message),
+                        ));
+                    }
                 }
             }
         }
-        let mut messages_to_send = mem::replace(&mut self.to_send, Vec::new());
         if !messages.is_empty() {
-            messages_to_send.push(ClientMessage::Event(Event::PlayerUpdated(
-                self.player.clone(),
-            )));
+            self.last_message_time = self.t;
+        }
+        self.update_reconnect();
+        let mut messages_to_send = mem::replace(&mut self.to_send, Vec::new());
+        self.timers.update(delta_time as f32);
+        if self.reconnecting.is_some() {
+            messages_to_send.clear();
+        } else {
+            if self.timers.player_update() {
+                messages_to_send.push(ClientMessage::Event(Event::PlayerUpdated(
+                    self.player.clone(),
+                )));
+            }
+            if !self.connection.is_local() {
+                messages_to_send.extend(self.timers.ping(self.t));
+                messages_to_send.extend(self.timers.ack(&mut self.event_sequence));
+            }
         }
         for message in messages_to_send {
             match &mut self.connection {
                 Connection::Remote(connection) => connection.send(message),
                 Connection::Local {
-                    next_tick: _,
-                    model,
+                    model, next_event, ..
                 } => {
-                    messages.push(ServerMessage::Update(
+                    messages.push(ServerMessage::local_update(
+                        next_event,
                         model.handle_message(self.player.id, message),
                     ));
                 }
@@ -884,7 +1127,10 @@ impl geng::State for GameState {
         }
         for message in messages {
             match message {
-                ServerMessage::Update(events) => {
+                ServerMessage::Update { first, events } => {
+                    let events = self
+                        .event_sequence
+                        .receive(first, events, &mut self.to_send);
                     for event in events {
                         match event {
                             Event::Hire(id) => {
@@ -899,10 +1145,7 @@ impl geng::State for GameState {
                                     if let Some(seat_index) = self.player.seat {
                                         self.player.seat = None;
                                         self.player.position =
-                                            self.model.seats[seat_index].leave_position;
-                                        self.to_send.push(ClientMessage::Event(Event::Order(
-                                            seat_index, None,
-                                        )));
+                                            self.model.level.seats[seat_index].leave_position;
                                     }
                                 }
                             }
@@ -915,6 +1158,7 @@ impl geng::State for GameState {
                                     self.text = "You were fired!";
                                     self.text_timer = 0.0;
                                     self.player.unemployed_time = Some(0.0);
+                                    self.player.pizza = None;
                                 }
                             }
                             Event::Interacted(typ) => {
@@ -938,23 +1182,83 @@ impl geng::State for GameState {
                             Event::PlayerJoined(_) => {
                                 self.assets.sounds.hi.play();
                             }
-                            Event::PlayerLeft(_) => {
-                                self.assets.sounds.bye.play();
+                            Event::PlayerUpdated(ref player) if player.id == self.player.id => {
+                                self.player.pizza = player.pizza.clone();
+                                self.player.score = player.score;
+                            }
+                            Event::PlayerLeft(player_id, reason) => {
+                                if reason != LeaveReason::Dismissed {
+                                    self.assets.sounds.bye.play();
+                                }
+                                let name = match self.model.players.get(&player_id) {
+                                    Some(player) if !player.name.is_empty() => player.name.clone(),
+                                    _ => "Somebody".to_owned(),
+                                };
+                                let text = match reason {
+                                    LeaveReason::Quit => Some(format!("{} left", name)),
+                                    LeaveReason::Timeout => {
+                                        Some(format!("{} lost connection", name))
+                                    }
+                                    LeaveReason::Kicked => Some(format!("{} was kicked", name)),
+                                    LeaveReason::Dismissed => None,
+                                };
+                                if let Some(text) = text {
+                                    self.notification =
+                                        Some((text, self.t + Self::NOTIFICATION_TIME));
+                                }
                             }
-                            Event::Eat => {
+                            Event::Delivered { cook, .. } => {
                                 self.assets.sounds.eat.play();
+                                if cook == self.player.id {
+                                    self.player.pizza = None;
+                                    self.player.score += 1;
+                                }
+                            }
+                            Event::Announcement(ref text) => {
+                                self.assets.sounds.bell.play();
+                                self.announcement =
+                                    Some((text.clone(), self.t + Self::ANNOUNCEMENT_TIME));
                             }
                             _ => {}
                         }
                         self.model.handle(event);
                     }
                 }
-                _ => unreachable!(),
+                ServerMessage::Snapshot { model, next_event } => {
+                    self.event_sequence.snapshot(next_event);
+                    self.apply_snapshot(model);
+                }
+                ServerMessage::Checksum {
+                    next_event,
+                    checksum,
+                } => {
+                    // Can only compare when we are exactly where the server was
+                    if self.event_sequence.next_event() == next_event
+                        && self.model.checksum() != checksum
+                    {
+                        self.to_send.push(ClientMessage::RequestSnapshot);
+                    }
+                }
+                ServerMessage::Kicked(reason) => {
+                    self.token = None;
+                    self.fail(ConnectionError::Kicked(reason));
+                }
+                ServerMessage::Pong(id) => {
+                    if let Some(latency) = self.timers.pong(id, self.t) {
+                        self.latency = Some(latency);
+                    }
+                }
+                ServerMessage::Hello(_)
+                | ServerMessage::ServerInfo(_)
+                | ServerMessage::RoomInfo(_)
+                | ServerMessage::Welcome(_) => {
+                    self.fail(ConnectionError::UnexpectedMessage);
+                }
             }
         }
         let delta_time = delta_time as f32;
         for player in self.model.players.values_mut() {
-            player.update(delta_time);
+            player.update(&self.model.config, delta_time);
         }
         self.update_player(delta_time);
 
@@ -962,15 +1266,16 @@ impl geng::State for GameState {
             if player.id == self.player.id {
                 continue;
             }
-            self.players
-                .entry(player.id)
-                .or_default()
-                .update(player, delta_time);
+            self.players.entry(player.id).or_default().update(
+                player,
+                delta_time,
+                self.latency.unwrap_or(0.0),
+            );
         }
         self.players
             .entry(self.player.id)
             .or_default()
-            .update(&self.player, delta_time);
+            .update(&self.player, delta_time, 0.0);
 
         self.update_camera(delta_time);
         self.model
@@ -993,8 +1298,8 @@ impl geng::State for GameState {
                 let position = position.map(|x| x as f32);
                 let position = self.camera.screen_to_world(self.framebuffer_size, position);
                 if let Some(seat_index) = self.player.seat {
-                    let seat = &self.model.seats[seat_index];
-                    if seat.order.is_none() {
+                    let seat = &self.model.level.seats[seat_index];
+                    if self.model.orders[seat_index].is_none() {
                         for button in self.model.buttons_for(seat) {
                             if (position - button.position).len() < button.radius {
                                 match button.typ {
@@ -1029,14 +1334,15 @@ impl geng::State for GameState {
                 | geng::Key::Down => {
                     if let Some(seat_index) = self.player.seat {
                         self.player.seat = None;
-                        self.player.position = self.model.seats[seat_index].leave_position;
-                        self.to_send
-                            .push(ClientMessage::Event(Event::Order(seat_index, None)));
+                        self.player.position = self.model.level.seats[seat_index].leave_position;
                     }
                 }
                 geng::Key::T => {
                     self.show_names = !self.show_names;
                 }
+                geng::Key::Escape if self.reconnecting.is_some() => {
+                    self.back_to_menu();
+                }
                 _ => {}
             },
             _ => {}
@@ -0,0 +1,130 @@
+use super::*;
+
+/// The static part of a restaurant. It is fully determined by its seed, so it never has to be
+/// sent over the network: the client generates the same level locally
+#[derive(Debug, Clone, Default)]
+pub struct Level {
+    pub seed: u64,
+    pub tables: Vec<Table>,
+    pub seats: Vec<Seat>,
+    pub kitchen: Vec<KitchenThing>,
+    pub pathfind_nodes: Vec<Vec2<f32>>,
+    pub pathfind_edges: Vec<Vec<usize>>,
+}
+
+impl Level {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tables = Vec::new();
+        let mut seats = Vec::new();
+        for x in -3..0 {
+            for y in std::array::IntoIter::new([-1, 1]) {
+                let table_pos = vec2(x as f32 * 4.0, y as f32 * 2.0);
+                let table_radius = 1.0;
+                tables.push(Table {
+                    position: table_pos,
+                    radius: table_radius,
+                    color: hsv(rng.gen_range(0.0..=1.0), 0.2, 1.0),
+                });
+                const SEATS: usize = 6;
+                for i in 0..SEATS {
+                    seats.push(Seat {
+                        position: table_pos + {
+                            let mut pos = Vec2::rotated(
+                                vec2(table_radius, 0.0),
+                                2.0 * f32::PI * i as f32 / SEATS as f32 + f32::PI / 2.0,
+                            );
+                            pos.x *= 1.1;
+                            pos.y *= 0.8;
+                            pos
+                        },
+                        leave_position: table_pos
+                            + Vec2::rotated(
+                                vec2(table_radius + 0.1 + 0.4 + 0.5 + 0.15, 0.0),
+                                2.0 * f32::PI * i as f32 / SEATS as f32 + f32::PI / 2.0,
+                            ),
+                        radius: 0.3,
+                        color: hsv(rng.gen_range(0.0..=1.0), 0.2, 0.8),
+                    });
+                }
+            }
+        }
+        let mut kitchen = vec![
+            KitchenThing {
+                typ: KitchenThingType::Dough,
+                position: vec2(1.0, -4.0),
+                radius: 0.8,
+            },
+            KitchenThing {
+                typ: KitchenThingType::TrashCan,
+                position: vec2(3.5, 4.0),
+                radius: 0.7,
+            },
+            KitchenThing {
+                typ: KitchenThingType::Oven,
+                position: vec2(1.0, 4.0),
+                radius: 1.0,
+            },
+        ];
+        {
+            let mut x = -3.0;
+            for ingredient in Ingredient::all() {
+                kitchen.push(KitchenThing {
+                    typ: KitchenThingType::IngredientBox(ingredient),
+                    position: vec2(4.0, x),
+                    radius: 0.7,
+                });
+                x += 1.5;
+            }
+        }
+        let mut pathfind_nodes = Vec::new();
+        let mut pathfind_edges;
+        {
+            const STEP: f32 = 0.5;
+            let mut x = -14.0;
+            while x <= 4.0 {
+                let mut y = -4.0;
+                while y <= 4.0 {
+                    let pos = vec2(x, y);
+                    let mut good = true;
+                    for thing in &kitchen {
+                        if (pos - thing.position).len() < thing.radius + 0.5 {
+                            good = false;
+                        }
+                    }
+                    for seat in &seats {
+                        if (pos - seat.position).len() < seat.radius + 0.5 {
+                            good = false;
+                        }
+                    }
+                    for table in &tables {
+                        if (pos - table.position).len() < table.radius + 0.5 {
+                            good = false;
+                        }
+                    }
+                    if good {
+                        pathfind_nodes.push(pos);
+                    }
+                    y += STEP;
+                }
+                x += STEP;
+            }
+            pathfind_edges = vec![vec![]; pathfind_nodes.len()];
+            for i in 0..pathfind_nodes.len() {
+                for j in 0..pathfind_nodes.len() {
+                    if (pathfind_nodes[i] - pathfind_nodes[j]).len() < STEP * 1.5 {
+                        pathfind_edges[i].push(j);
+                    }
+                }
+            }
+        }
+        Self {
+            seed,
+            tables,
+            seats,
+            kitchen,
+            pathfind_nodes,
+            pathfind_edges,
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use super::*;
+
+/// A fake player that wanders around and pokes the kitchen, measuring what it gets back
+struct SimulatedClient {
+    player: Player,
+    model: Model,
+    connection: geng::net::client::Connection<ServerMessage, ClientMessage>,
+    timers: ClientTimers,
+    next_direction_change: f32,
+    next_intent: f32,
+    event_sequence: EventSequence,
+}
+
+#[derive(Default)]
+struct Stats {
+    messages: usize,
+    bytes: usize,
+    latencies: Vec<f32>,
+}
+
+impl SimulatedClient {
+    fn connect(opt: &Opt, room: &str) -> Self {
+        let name = "load test".to_owned();
+        let (welcome, connection) =
+            match futures::executor::block_on(connect(opt, room.to_owned(), false, None, name)) {
+                Ok(result) => result,
+                Err(error) => panic!("Failed to connect: {}", error),
+            };
+        let connection = match connection {
+            Connection::Remote(connection) => connection,
+            Connection::Local { .. } => unreachable!(),
+        };
+        let event_sequence = EventSequence::new(welcome.next_event);
+        let mut player = welcome.model.players[&welcome.player_id].clone();
+        player.name = format!("load test {}", player.id.raw());
+        Self {
+            player,
+            model: welcome.model,
+            connection,
+            timers: ClientTimers::staggered(),
+            next_direction_change: 0.0,
+            next_intent: global_rng().gen_range(0.0..=1.0),
+            event_sequence,
+        }
+    }
+
+    fn update(&mut self, t: f32, delta_time: f32, stats: &mut Stats) {
+        let mut to_send = Vec::new();
+        for message in self.connection.new_messages() {
+            stats.messages += 1;
+            stats.bytes += bincode::serialized_size(&message).unwrap_or(0) as usize;
+            match message {
+                ServerMessage::Update { first, events } => {
+                    for event in self.event_sequence.receive(first, events, &mut to_send) {
+                        self.model.handle(event);
+                    }
+                }
+                ServerMessage::Snapshot {
+                    mut model,
+                    next_event,
+                } => {
+                    model.level = self.model.level.clone();
+                    self.model = model;
+                    self.event_sequence.snapshot(next_event);
+                }
+                ServerMessage::Pong(id) => {
+                    stats.latencies.extend(self.timers.pong(id, t));
+                }
+                _ => {}
+            }
+        }
+        for message in to_send {
+            self.connection.send(message);
+        }
+        if let Some(player) = self.model.players.get(&self.player.id) {
+            self.player.pizza = player.pizza.clone();
+            self.player.unemployed_time = player.unemployed_time;
+        }
+
+        self.next_direction_change -= delta_time;
+        if self.next_direction_change <= 0.0 {
+            self.next_direction_change = global_rng().gen_range(0.5..=2.0);
+            self.player.target_velocity =
+                Vec2::rotated(vec2(1.0, 0.0), global_rng().gen_range(0.0..=2.0 * f32::PI));
+        }
+        self.player.update(&self.model.config, delta_time);
+
+        self.timers.update(delta_time);
+        if self.timers.player_update() {
+            self.connection
+                .send(ClientMessage::Event(Event::PlayerUpdated(
+                    self.player.clone(),
+                )));
+        }
+        self.next_intent -= delta_time;
+        if self.next_intent <= 0.0 {
+            self.next_intent = global_rng().gen_range(0.5..=1.5);
+            let intent = if global_rng().gen_bool(0.5) {
+                Intent::Interact(global_rng().gen_range(0..self.model.level.kitchen.len()))
+            } else {
+                Intent::Deliver(global_rng().gen_range(0..self.model.level.seats.len()))
+            };
+            self.connection.send(ClientMessage::Intent(intent));
+        }
+        if let Some(ping) = self.timers.ping(t) {
+            self.connection.send(ping);
+        }
+        if let Some(ack) = self.timers.ack(&mut self.event_sequence) {
+            self.connection.send(ack);
+        }
+    }
+}
+
+/// Connects a bunch of simulated clients to the server and reports how it copes, runs until killed
+pub fn run(opt: &Opt, clients: usize) {
+    const REPORT_INTERVAL: f32 = 5.0;
+    const FRAME_TIME: f32 = 1.0 / 60.0;
+
+    let room = normalize_room_code(opt.room());
+    let mut simulated: Vec<SimulatedClient> = Vec::new();
+    for _ in 0..clients {
+        simulated.push(SimulatedClient::connect(opt, &room));
+    }
+    println!("Connected {} clients to room {:?}", simulated.len(), room);
+
+    // Waiting for the answer here would stall every simulated client for a round trip
+    let tick_lag = Arc::new(Mutex::new("unknown".to_owned()));
+    std::thread::spawn({
+        let opt = opt.clone();
+        let tick_lag = tick_lag.clone();
+        move || loop {
+            let lag = match futures::executor::block_on(query_room(&opt, room.clone())) {
+                Ok(Some(info)) => format!("{:.1}ms", info.tick_lag * 1000.0),
+                Ok(None) => "room is gone".to_owned(),
+                Err(error) => error.to_string(),
+            };
+            *tick_lag.lock().unwrap() = lag;
+            std::thread::sleep(std::time::Duration::from_secs_f32(REPORT_INTERVAL));
+        }
+    });
+
+    let start = std::time::Instant::now();
+    let mut last_frame = start;
+    let mut next_report = REPORT_INTERVAL;
+    let mut stats = Stats::default();
+    loop {
+        let now = std::time::Instant::now();
+        let delta_time = (now - last_frame).as_secs_f32();
+        last_frame = now;
+        let t = (now - start).as_secs_f32();
+        for client in &mut simulated {
+            client.update(t, delta_time, &mut stats);
+        }
+        if t >= next_report {
+            next_report += REPORT_INTERVAL;
+            let latencies = mem::take(&mut stats.latencies);
+            println!(
+                "{:.0}s: {:.0} msg/s, {:.1} KiB/s, {:.0} B/msg, latency avg {:.1}ms max {:.1}ms, tick lag {}",
+                t,
+                stats.messages as f32 / REPORT_INTERVAL,
+                stats.bytes as f32 / REPORT_INTERVAL / 1024.0,
+                stats.bytes as f32 / stats.messages.max(1) as f32,
+                latencies.iter().sum::<f32>() / latencies.len().max(1) as f32 * 1000.0,
+                latencies.iter().copied().fold(0.0, f32::max) * 1000.0,
+                tick_lag.lock().unwrap(),
+            );
+            stats = Stats::default();
+        }
+        let elapsed = now.elapsed().as_secs_f32();
+        if elapsed < FRAME_TIME {
+            std::thread::sleep(std::time::Duration::from_secs_f32(FRAME_TIME - elapsed));
+        }
+    }
+}
@@ -1,69 +1,300 @@
 use super::*;
 
+type RemoteConnection = geng::net::client::Connection<ServerMessage, ClientMessage>;
+
+#[derive(Debug, Clone)]
+pub enum ConnectionError {
+    VersionMismatch {
+        server_version: u32,
+    },
+    UnexpectedMessage,
+    Closed,
+    Timeout,
+    /// Gave up on reconnecting in the middle of a game
+    Lost,
+    Kicked(String),
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::VersionMismatch { server_version } if *server_version > PROTOCOL_VERSION => {
+                write!(f, "Your game is outdated, please update")
+            }
+            Self::VersionMismatch { .. } => {
+                write!(f, "The server is running an older version of the game")
+            }
+            Self::UnexpectedMessage => write!(f, "The server sent something unexpected"),
+            Self::Closed => write!(f, "The server closed the connection"),
+            Self::Timeout => write!(f, "The server is not responding"),
+            Self::Lost => write!(f, "Lost connection to the server"),
+            Self::Kicked(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Says hello and makes sure the server speaks the same protocol
+async fn handshake(mut connection: RemoteConnection) -> Result<RemoteConnection, ConnectionError> {
+    connection.send(ClientMessage::Hello(PROTOCOL_VERSION));
+    let (message, connection) = connection.into_future().await;
+    match message {
+        Some(ServerMessage::Hello(version)) if version == PROTOCOL_VERSION => Ok(connection),
+        Some(ServerMessage::Hello(server_version)) => {
+            Err(ConnectionError::VersionMismatch { server_version })
+        }
+        Some(_) => Err(ConnectionError::UnexpectedMessage),
+        None => Err(ConnectionError::Closed),
+    }
+}
+
+pub type ConnectionFuture =
+    Pin<Box<dyn Future<Output = Result<(WelcomeMessage, Connection), ConnectionError>>>>;
+
+/// Connects to the server and joins a room, resolving once the welcome arrives
+pub fn connect(
+    opt: &Opt,
+    room: String,
+    public: bool,
+    token: Option<SessionToken>,
+    name: String,
+) -> ConnectionFuture {
+    Box::pin(
+        geng::net::client::connect(&opt.server_url()).then(move |connection| async move {
+            let mut connection = match handshake(connection).await {
+                Ok(connection) => connection,
+                Err(error) => return Err(error),
+            };
+            connection.send(ClientMessage::Join {
+                room,
+                public,
+                token,
+                name,
+            });
+            let (message, connection) = connection.into_future().await;
+            match message {
+                Some(ServerMessage::Welcome(mut welcome)) => {
+                    welcome.model.load_level();
+                    Ok((welcome, Connection::Remote(connection)))
+                }
+                Some(ServerMessage::Kicked(reason)) => Err(ConnectionError::Kicked(reason)),
+                Some(_) => Err(ConnectionError::UnexpectedMessage),
+                None => Err(ConnectionError::Closed),
+            }
+        }),
+    )
+}
+
+/// Looks up a single room by its code without joining it, private rooms too
+pub fn query_room(
+    opt: &Opt,
+    room: String,
+) -> Pin<Box<dyn Future<Output = Result<Option<RoomInfo>, ConnectionError>>>> {
+    Box::pin(
+        geng::net::client::connect(&opt.server_url()).then(|connection| async move {
+            let mut connection = match handshake(connection).await {
+                Ok(connection) => connection,
+                Err(error) => return Err(error),
+            };
+            connection.send(ClientMessage::QueryRoom(room));
+            let (message, _) = connection.into_future().await;
+            match message {
+                Some(ServerMessage::RoomInfo(info)) => Ok(info),
+                Some(_) => Err(ConnectionError::UnexpectedMessage),
+                None => Err(ConnectionError::Closed),
+            }
+        }),
+    )
+}
+
+/// Asks the server which rooms it has without joining any of them
+pub fn query_server_info(
+    opt: &Opt,
+) -> Pin<Box<dyn Future<Output = Result<ServerInfo, ConnectionError>>>> {
+    Box::pin(
+        geng::net::client::connect(&opt.server_url()).then(|connection| async move {
+            let mut connection = match handshake(connection).await {
+                Ok(connection) => connection,
+                Err(error) => return Err(error),
+            };
+            connection.send(ClientMessage::QueryServerInfo);
+            let (message, _) = connection.into_future().await;
+            match message {
+                Some(ServerMessage::ServerInfo(info)) => Ok(info),
+                Some(_) => Err(ConnectionError::UnexpectedMessage),
+                None => Err(ConnectionError::Closed),
+            }
+        }),
+    )
+}
+
 pub struct ConnectingState {
     geng: Geng,
     assets: Rc<Assets>,
     opt: Rc<Opt>,
     name: String,
-    connection: Option<Pin<Box<dyn Future<Output = (WelcomeMessage, Connection)>>>>,
+    room: String,
+    public: bool,
+    connection: Option<ConnectionFuture>,
+    error: Option<ConnectionError>,
+    time: f64,
     transition: Option<geng::Transition>,
     color: Color<f32>,
+    framebuffer_size: Vec2<f32>,
 }
 
 impl ConnectingState {
+    const TIMEOUT: f64 = 10.0;
+
     pub fn new(
         geng: &Geng,
         assets: &Rc<Assets>,
         opt: &Rc<Opt>,
         name: String,
         color: Color<f32>,
+        room: String,
+        public: bool,
     ) -> Self {
-        let addr = format!("{}://{}", option_env!("WSS").unwrap_or("ws"), opt.addr());
-        let connection = Box::pin(
-            geng::net::client::connect(&addr)
-                .then(|connection| async move {
-                    let (message, connection) = connection.into_future().await;
-                    let welcome = match message {
-                        Some(ServerMessage::Welcome(message)) => message,
-                        _ => unreachable!(),
-                    };
-                    (welcome, connection)
-                })
-                .map(|(welcome, connection)| (welcome, Connection::Remote(connection))),
-        );
+        let connection = connect(opt, room.clone(), public, None, name.clone());
         Self {
             geng: geng.clone(),
             assets: assets.clone(),
             opt: opt.clone(),
             name,
+            room,
+            public,
             color,
             connection: Some(connection),
+            error: None,
+            time: 0.0,
             transition: None,
+            framebuffer_size: vec2(1.0, 1.0),
         }
     }
-}
-
-impl geng::State for ConnectingState {
-    fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
-        let framebuffer_size = framebuffer.size();
-        ugli::clear(framebuffer, Some(Color::WHITE), None);
+    /// Shows the error instead of connecting
+    pub fn failed(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        opt: &Rc<Opt>,
+        name: String,
+        color: Color<f32>,
+        room: String,
+        public: bool,
+        error: ConnectionError,
+    ) -> Self {
+        Self {
+            geng: geng.clone(),
+            assets: assets.clone(),
+            opt: opt.clone(),
+            name,
+            room,
+            public,
+            color,
+            connection: None,
+            error: Some(error),
+            time: 0.0,
+            transition: None,
+            framebuffer_size: vec2(1.0, 1.0),
+        }
+    }
+    fn retry(&mut self) {
+        self.connection = Some(connect(
+            &self.opt,
+            self.room.clone(),
+            self.public,
+            None,
+            self.name.clone(),
+        ));
+        self.error = None;
+        self.time = 0.0;
+    }
+    fn back_to_menu(&mut self) {
+        self.transition = Some(geng::Transition::Switch(Box::new(Menu::new(
+            &self.geng,
+            &self.assets,
+            &self.opt,
+        ))));
+    }
+    fn retry_position(&self) -> Vec2<f32> {
+        self.framebuffer_size / 2.0 - vec2(100.0, 80.0)
+    }
+    fn back_position(&self) -> Vec2<f32> {
+        self.framebuffer_size / 2.0 - vec2(-100.0, 80.0)
+    }
+    fn hovered(&self, position: Vec2<f32>) -> bool {
+        let mouse = self.geng.window().mouse_pos().map(|x| x as f32);
+        (mouse.x - position.x).abs() < 80.0 && mouse.y > position.y && mouse.y < position.y + 32.0
+    }
+    fn draw_button(&self, framebuffer: &mut ugli::Framebuffer, text: &str, position: Vec2<f32>) {
         self.assets.font.draw_aligned(
             framebuffer,
             &geng::PixelPerfectCamera,
-            "Connecting to the server...",
-            framebuffer_size.map(|x| x as f32) / 2.0,
+            text,
+            position,
             0.5,
-            40.0,
-            Color::BLACK,
+            32.0,
+            if self.hovered(position) {
+                Color::BLUE
+            } else {
+                Color::BLACK
+            },
         );
     }
-    fn update(&mut self, delta_time: f64) {}
+}
+
+impl geng::State for ConnectingState {
+    fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        self.framebuffer_size = framebuffer.size().map(|x| x as f32);
+        ugli::clear(framebuffer, Some(Color::WHITE), None);
+        let center = self.framebuffer_size / 2.0;
+        match &self.error {
+            Some(error) => {
+                self.assets.font.draw_aligned(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    &error.to_string(),
+                    center,
+                    0.5,
+                    40.0,
+                    Color::RED,
+                );
+                self.draw_button(framebuffer, "RETRY", self.retry_position());
+            }
+            None => {
+                self.assets.font.draw_aligned(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    "Connecting to the server...",
+                    center,
+                    0.5,
+                    40.0,
+                    Color::BLACK,
+                );
+            }
+        }
+        self.draw_button(framebuffer, "BACK", self.back_position());
+    }
+    fn update(&mut self, delta_time: f64) {
+        self.time += delta_time;
+        if self.connection.is_some() && self.time > Self::TIMEOUT {
+            self.connection = None;
+            self.error = Some(ConnectionError::Timeout);
+        }
+    }
     fn handle_event(&mut self, event: geng::Event) {
         match event {
-            geng::Event::KeyDown { key, .. } => match key {
-                geng::Key::Escape => {
-                    self.transition = Some(geng::Transition::Pop);
+            geng::Event::MouseDown {
+                button: geng::MouseButton::Left,
+                ..
+            } => {
+                if self.hovered(self.back_position()) {
+                    self.back_to_menu();
+                } else if self.error.is_some() && self.hovered(self.retry_position()) {
+                    self.retry();
                 }
+            }
+            geng::Event::KeyDown { key, .. } => match key {
+                geng::Key::Escape => self.back_to_menu(),
+                geng::Key::R | geng::Key::Enter if self.error.is_some() => self.retry(),
                 _ => {}
             },
             _ => {}
@@ -71,19 +302,29 @@ impl geng::State for ConnectingState {
     }
     fn transition(&mut self) -> Option<geng::Transition> {
         if let Some(connection) = &mut self.connection {
-            if let std::task::Poll::Ready((welcome, connection)) =
+            if let std::task::Poll::Ready(result) =
                 connection
                     .as_mut()
                     .poll(&mut std::task::Context::from_waker(
                         futures::task::noop_waker_ref(),
                     ))
             {
+                self.connection = None;
+                let (welcome, connection) = match result {
+                    Ok(result) => result,
+                    Err(error) => {
+                        self.error = Some(error);
+                        return self.transition.take();
+                    }
+                };
                 return Some(geng::Transition::Switch(Box::new(GameState::new(
                     &self.geng,
                     &self.assets,
                     &self.opt,
                     &self.name,
                     self.color,
+                    self.room.clone(),
+                    self.public,
                     welcome,
                     connection,
                 ))));
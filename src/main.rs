@@ -2,7 +2,12 @@ use geng::prelude::*;
 
 use std::collections::BTreeSet;
 
+pub mod bot;
+pub mod config;
 pub mod game_state;
+pub mod level;
+#[cfg(not(target_arch = "wasm32"))]
+mod load_test;
 pub mod lobby;
 mod menu;
 pub mod model;
@@ -10,7 +15,10 @@ pub mod net;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod server;
 
+pub use bot::Bot;
+pub use config::*;
 pub use game_state::GameState;
+pub use level::Level;
 pub use lobby::*;
 use menu::*;
 pub use model::*;
@@ -145,7 +153,7 @@ impl Assets {
     }
 }
 
-#[derive(Clap)]
+#[derive(Clap, Clone)]
 pub struct Opt {
     #[clap(long)]
     addr: Option<String>,
@@ -153,20 +161,106 @@ pub struct Opt {
     server: bool,
     #[clap(long)]
     with_server: bool,
+    #[clap(long)]
+    room: Option<String>,
+    /// Full url to connect to like `wss://example.com:1155`, overrides `--addr` on the client
+    #[clap(long = "server-url")]
+    url: Option<String>,
+    /// Server config file in RON format, `--addr` and `--min-players` take priority over it
+    #[clap(long)]
+    config: Option<std::path::PathBuf>,
+    /// Server spawns bots while there are fewer players than this
+    #[clap(long)]
+    min_players: Option<usize>,
+    /// Instead of opening a window, connect this many simulated clients and report server performance
+    #[clap(long)]
+    load_test: Option<usize>,
+}
+
+pub fn default_addr() -> &'static str {
+    option_env!("SERVER_ADDR").unwrap_or("127.0.0.1:1155")
 }
 
 impl Opt {
     pub fn addr(&self) -> &str {
         match &self.addr {
             Some(addr) => addr,
-            None => option_env!("SERVER_ADDR").unwrap_or("127.0.0.1:1155"),
+            None => default_addr(),
+        }
+    }
+    /// Has to be called before changing into the assets directory,
+    /// relative paths are given from where we were started
+    pub fn server_config(&self) -> ServerConfig {
+        let start_dir = std::env::current_dir().unwrap();
+        let mut config = match &self.config {
+            Some(path) => {
+                let path = start_dir.join(path);
+                ServerConfig::load(&path)
+                    .unwrap_or_else(|e| panic!("Failed to load config {:?}: {}", path, e))
+            }
+            None => {
+                let mut config = ServerConfig::default();
+                // A local game shouldn't leave a ban list lying around
+                if self.with_server {
+                    config.ban_list = None;
+                }
+                config
+            }
+        };
+        config.resolve_paths(&start_dir);
+        if let Some(addr) = &self.addr {
+            config.addr = addr.clone();
+        }
+        if let Some(min_players) = self.min_players {
+            config.min_players = min_players;
         }
+        config
+    }
+    pub fn server_url(&self) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => format!("{}://{}", option_env!("WSS").unwrap_or("ws"), self.addr()),
+        }
+    }
+    /// The web build has no command line, `?server-url=...&room=...` is used instead
+    #[cfg(target_arch = "wasm32")]
+    fn apply_query_params(&mut self) {
+        let search = match web_sys::window().and_then(|window| window.location().search().ok()) {
+            Some(search) => search,
+            None => return,
+        };
+        let params = match web_sys::UrlSearchParams::new_with_str(&search) {
+            Ok(params) => params,
+            Err(_) => return,
+        };
+        if let Some(url) = params.get("server-url") {
+            self.url = Some(url);
+        }
+        if let Some(room) = params.get("room") {
+            self.room = Some(room);
+        }
+    }
+    pub fn room(&self) -> &str {
+        self.room.as_deref().unwrap_or("")
     }
 }
 
 fn main() {
     logger::init().unwrap();
     geng::setup_panic_handler();
+    let opt: Opt = Clap::parse();
+    #[cfg(target_arch = "wasm32")]
+    let opt = {
+        let mut opt = opt;
+        opt.apply_query_params();
+        opt
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let server_config = if opt.server || opt.with_server {
+        Some(opt.server_config())
+    } else {
+        None
+    };
     if let Some(dir) = std::env::var_os("CARGO_MANIFEST_DIR") {
         std::env::set_current_dir(std::path::Path::new(&dir).join("static")).unwrap();
     } else {
@@ -177,15 +271,21 @@ fn main() {
             }
         }
     }
-    let opt: Opt = Clap::parse();
     let opt = Rc::new(opt);
     if opt.server {
         #[cfg(not(target_arch = "wasm32"))]
-        Server::new(opt.addr(), Model::new()).run();
+        {
+            let server = Server::new(server_config.unwrap());
+            server.spawn_console();
+            server.run();
+        }
+    } else if let Some(clients) = opt.load_test {
+        #[cfg(not(target_arch = "wasm32"))]
+        load_test::run(&opt, clients);
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if opt.with_server {
-            let server = Server::new(opt.addr(), Model::new());
+            let server = Server::new(server_config.unwrap());
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -205,19 +305,6 @@ fn main() {
                     assets.floor.set_wrap_mode(ugli::WrapMode::Repeat);
                     assets.sounds.music.looped = true;
                     Menu::new(&geng, &Rc::new(assets), &opt)
-                    // let mut model = Model::new();
-                    // let (welcome, _) = model.welcome();
-                    // GameState::new(
-                    //     &geng,
-                    //     &Rc::new(assets),
-                    //     &opt,
-                    //     None,
-                    //     welcome,
-                    //     Connection::Local {
-                    //         next_tick: 0.0,
-                    //         model,
-                    //     },
-                    // )
                     // Lobby::new(&geng, Rc::new(assets), &opt)
                 }
             }),
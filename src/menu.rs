@@ -1,19 +1,36 @@
 use super::*;
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Field {
+    Name,
+    RoomCode,
+}
+
 pub struct Menu {
     geng: Geng,
     assets: Rc<Assets>,
     opt: Rc<Opt>,
     start: bool,
+    practice: bool,
     camera: geng::Camera2d,
     framebuffer_size: Vec2<f32>,
     name: String,
     color: Color<f32>,
     pallete: ugli::Texture,
     hue: f32,
+    focus: Field,
+    room_code: String,
+    /// Whether a room created from here shows up in everyone's room list
+    list_room: bool,
+    rooms: Option<Result<Vec<RoomInfo>, ConnectionError>>,
+    server_info: Option<Pin<Box<dyn Future<Output = Result<ServerInfo, ConnectionError>>>>>,
 }
 
 impl Menu {
+    const ROOM_LIST_X: f32 = 540.0;
+    const ROOM_LIST_TOP: f32 = 400.0;
+    const ROOM_ROW_HEIGHT: f32 = 28.0;
+
     pub fn new(geng: &Geng, assets: &Rc<Assets>, opt: &Rc<Opt>) -> Self {
         let hue = global_rng().gen_range(0.0..=1.0);
         Self {
@@ -21,6 +38,7 @@ impl Menu {
             assets: assets.clone(),
             opt: opt.clone(),
             start: false,
+            practice: false,
             camera: geng::Camera2d::new(vec2(400.0, 300.0), 600.0, 8000.0),
             framebuffer_size: vec2(1.0, 1.0),
             name: String::new(),
@@ -29,29 +47,116 @@ impl Menu {
                 hsv(pos.x as f32 / 127.0, 1.0, 1.0)
             }),
             hue,
+            focus: Field::Name,
+            room_code: opt.room().to_owned(),
+            list_room: false,
+            rooms: None,
+            server_info: Some(query_server_info(opt)),
         }
     }
-    fn start_hovered(&self) -> bool {
-        let pos = self.camera.screen_to_world(
+    fn mouse_world_pos(&self) -> Vec2<f32> {
+        self.camera.screen_to_world(
             self.framebuffer_size,
             self.geng.window().mouse_pos().map(|x| x as f32),
-        );
+        )
+    }
+    fn start_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
         pos.y > 100.0 && pos.y < 132.0
     }
+    fn practice_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
+        pos.y > 50.0 && pos.y < 82.0
+    }
+    fn name_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
+        pos.y > 460.0 && pos.y < 500.0
+    }
+    fn room_code_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
+        pos.x < 260.0 && pos.y > 340.0 && pos.y < 400.0
+    }
+    fn list_room_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
+        pos.x < 260.0 && pos.y > 300.0 && pos.y < 330.0
+    }
+    /// The public room is always listed, the toggle only matters for the others
+    fn room_code_is_public(&self) -> bool {
+        normalize_room_code(&self.room_code) == PUBLIC_ROOM
+    }
+    fn refresh_hovered(&self) -> bool {
+        let pos = self.mouse_world_pos();
+        pos.x > Self::ROOM_LIST_X
+            && pos.y > Self::ROOM_LIST_TOP
+            && pos.y < Self::ROOM_LIST_TOP + 32.0
+    }
+    fn hovered_room(&self) -> Option<usize> {
+        let pos = self.mouse_world_pos();
+        if pos.x < Self::ROOM_LIST_X || pos.y > Self::ROOM_LIST_TOP {
+            return None;
+        }
+        let index = ((Self::ROOM_LIST_TOP - pos.y) / Self::ROOM_ROW_HEIGHT) as usize;
+        match &self.rooms {
+            Some(Ok(rooms)) if index < rooms.len() => Some(index),
+            _ => None,
+        }
+    }
+    fn draw_field(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        text: &str,
+        placeholder: &str,
+        position: Vec2<f32>,
+        field: Field,
+    ) {
+        let focused = self.focus == field;
+        self.assets.font.draw_aligned(
+            framebuffer,
+            &self.camera,
+            if text.is_empty() { placeholder } else { text },
+            position,
+            0.5,
+            32.0,
+            if text.is_empty() {
+                Color::GRAY
+            } else if focused {
+                Color::BLACK
+            } else {
+                Color::rgb(0.3, 0.3, 0.3)
+            },
+        );
+        if focused {
+            self.geng.draw_2d().quad(
+                framebuffer,
+                &self.camera,
+                AABB::pos_size(position - vec2(80.0, 6.0), vec2(160.0, 2.0)),
+                Color::BLACK,
+            );
+        }
+    }
 }
 
 impl geng::State for Menu {
     fn update(&mut self, _delta_time: f64) {
+        if let Some(server_info) = &mut self.server_info {
+            if let std::task::Poll::Ready(info) =
+                server_info
+                    .as_mut()
+                    .poll(&mut std::task::Context::from_waker(
+                        futures::task::noop_waker_ref(),
+                    ))
+            {
+                self.rooms = Some(info.map(|info| info.rooms));
+                self.server_info = None;
+            }
+        }
         if self
             .geng
             .window()
             .is_button_pressed(geng::MouseButton::Left)
         {
-            let pos = self.camera.screen_to_world(
-                self.framebuffer_size,
-                self.geng.window().mouse_pos().map(|x| x as f32),
-            );
-            if pos.y > 420.0 && pos.y < 452.0 {
+            let pos = self.mouse_world_pos();
+            if pos.x > 300.0 && pos.x < 500.0 && pos.y > 420.0 && pos.y < 452.0 {
                 self.hue = clamp((pos.x - 300.0) / 200.0, 0.0..=1.0);
                 self.color = hsv(self.hue, 1.0, 1.5);
             }
@@ -59,13 +164,23 @@ impl geng::State for Menu {
     }
 
     fn transition(&mut self) -> Option<geng::Transition> {
-        if self.start {
+        if self.practice {
+            Some(geng::Transition::Switch(Box::new(GameState::practice(
+                &self.geng,
+                &self.assets,
+                &self.opt,
+                &self.name,
+                self.color,
+            ))))
+        } else if self.start {
             Some(geng::Transition::Switch(Box::new(ConnectingState::new(
                 &self.geng,
                 &self.assets,
                 &self.opt,
                 self.name.clone(),
                 self.color,
+                self.room_code.clone(),
+                self.list_room,
             ))))
         } else {
             None
@@ -105,27 +220,108 @@ impl geng::State for Menu {
             ),
             Color::BLACK,
         );
-        if self.name.is_empty() {
-            self.assets.font.draw_aligned(
-                framebuffer,
-                &self.camera,
-                "type your name here",
-                vec2(400.0, 468.0),
-                0.5,
-                32.0,
-                Color::GRAY,
-            );
-        } else {
-            self.assets.font.draw_aligned(
+        self.draw_field(
+            framebuffer,
+            &self.name,
+            "type your name here",
+            vec2(400.0, 468.0),
+            Field::Name,
+        );
+
+        self.assets.font.draw_aligned(
+            framebuffer,
+            &self.camera,
+            "room code",
+            vec2(140.0, 380.0),
+            0.5,
+            24.0,
+            Color::BLACK,
+        );
+        self.draw_field(
+            framebuffer,
+            &self.room_code,
+            PUBLIC_ROOM,
+            vec2(140.0, 348.0),
+            Field::RoomCode,
+        );
+        self.assets.font.draw_aligned(
+            framebuffer,
+            &self.camera,
+            if self.room_code_is_public() || self.list_room {
+                "listed in rooms"
+            } else {
+                "not listed in rooms"
+            },
+            vec2(140.0, 308.0),
+            0.5,
+            20.0,
+            if self.room_code_is_public() {
+                Color::GRAY
+            } else if self.list_room_hovered() {
+                Color::BLUE
+            } else {
+                Color::BLACK
+            },
+        );
+
+        self.assets.font.draw(
+            framebuffer,
+            &self.camera,
+            if self.server_info.is_some() {
+                "ROOMS (loading...)"
+            } else {
+                "ROOMS (refresh)"
+            },
+            vec2(Self::ROOM_LIST_X, Self::ROOM_LIST_TOP + 8.0),
+            24.0,
+            if self.refresh_hovered() {
+                Color::BLUE
+            } else {
+                Color::BLACK
+            },
+        );
+        if let Some(Err(error)) = &self.rooms {
+            self.assets.font.draw(
                 framebuffer,
                 &self.camera,
-                &self.name,
-                vec2(400.0, 468.0),
-                0.5,
-                32.0,
-                Color::BLACK,
+                &error.to_string(),
+                vec2(
+                    Self::ROOM_LIST_X,
+                    Self::ROOM_LIST_TOP - Self::ROOM_ROW_HEIGHT,
+                ),
+                20.0,
+                Color::RED,
             );
         }
+        if let Some(Ok(rooms)) = &self.rooms {
+            let hovered_room = self.hovered_room();
+            for (index, room) in rooms.iter().enumerate() {
+                self.assets.font.draw(
+                    framebuffer,
+                    &self.camera,
+                    &format!(
+                        "{}: {} cooks, {} customers ({} bots)",
+                        room.name,
+                        room.employees,
+                        room.customers(),
+                        room.bots,
+                    ),
+                    vec2(
+                        Self::ROOM_LIST_X,
+                        Self::ROOM_LIST_TOP - Self::ROOM_ROW_HEIGHT * (index + 1) as f32,
+                    ),
+                    20.0,
+                    if hovered_room == Some(index) {
+                        Color::BLUE
+                    } else if normalize_room_code(&self.room_code) == room.name {
+                        Color::BLACK
+                    } else {
+                        Color::GRAY
+                    },
+                );
+            }
+        }
+
         self.assets.font.draw_aligned(
             framebuffer,
             &self.camera,
@@ -139,6 +335,19 @@ impl geng::State for Menu {
                 Color::BLACK
             },
         );
+        self.assets.font.draw_aligned(
+            framebuffer,
+            &self.camera,
+            "PRACTICE",
+            vec2(400.0, 50.0),
+            0.5,
+            24.0,
+            if self.practice_hovered() {
+                Color::BLUE
+            } else {
+                Color::GRAY
+            },
+        );
     }
 
     fn handle_event(&mut self, event: geng::Event) {
@@ -146,16 +355,47 @@ impl geng::State for Menu {
             geng::Event::MouseDown {
                 button: geng::MouseButton::Left,
                 ..
-            } if self.start_hovered() => {
-                self.start = true;
+            } => {
+                if self.start_hovered() {
+                    self.start = true;
+                } else if self.practice_hovered() {
+                    self.practice = true;
+                } else if self.name_hovered() {
+                    self.focus = Field::Name;
+                } else if self.room_code_hovered() {
+                    self.focus = Field::RoomCode;
+                } else if self.list_room_hovered() {
+                    if !self.room_code_is_public() {
+                        self.list_room = !self.list_room;
+                    }
+                } else if self.refresh_hovered() {
+                    if self.server_info.is_none() {
+                        self.server_info = Some(query_server_info(&self.opt));
+                    }
+                } else if let Some(index) = self.hovered_room() {
+                    if let Some(Ok(rooms)) = &self.rooms {
+                        self.room_code = rooms[index].name.clone();
+                    }
+                }
             }
             geng::Event::KeyDown { key } => {
+                if key == geng::Key::Tab {
+                    self.focus = match self.focus {
+                        Field::Name => Field::RoomCode,
+                        Field::RoomCode => Field::Name,
+                    };
+                    return;
+                }
+                let (text, max_len) = match self.focus {
+                    Field::Name => (&mut self.name, MAX_NAME_LEN),
+                    Field::RoomCode => (&mut self.room_code, MAX_ROOM_CODE_LEN),
+                };
                 if key == geng::Key::Backspace {
-                    self.name.pop();
+                    text.pop();
                 }
                 let key_string = format!("{:?}", key);
-                if key_string.len() == 1 {
-                    self.name.push_str(&key_string);
+                if key_string.len() == 1 && text.len() < max_len {
+                    text.push_str(&key_string);
                 }
             }
             _ => {}
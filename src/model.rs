@@ -52,11 +52,12 @@ pub struct Player {
     pub t: f32,
     pub left: bool,
     pub name: String,
+    pub bot: bool,
 }
 
 impl Player {
-    pub const SPEED: f32 = 6.0;
-    pub const ACCELERATION: f32 = 50.0;
+    /// How far from touching something a player can be and still interact with it.
+    pub const TOUCH_TOLERANCE: f32 = 0.5;
     pub fn new(id_gen: &mut IdGen) -> Self {
         let mut player = Self {
             id: id_gen.gen(),
@@ -72,13 +73,14 @@ impl Player {
             color: hsv(global_rng().gen_range(0.0..=1.0), 1.0, 1.5),
             left: global_rng().gen_bool(0.5),
             name: String::new(),
+            bot: false,
         };
         player
     }
-    pub fn update(&mut self, delta_time: f32) {
+    pub fn update(&mut self, config: &Config, delta_time: f32) {
         self.t += delta_time;
-        self.velocity += (self.target_velocity * Self::SPEED - self.velocity)
-            .clamp(Self::ACCELERATION * delta_time);
+        self.velocity += (self.target_velocity * config.player_speed - self.velocity)
+            .clamp(config.player_acceleration * delta_time);
         self.position += self.velocity * delta_time;
         self.position.x = clamp(self.position.x, -14.0..=4.0);
         self.position.y = clamp(self.position.y, -4.0..=4.0);
@@ -90,6 +92,61 @@ impl Player {
         }
     }
 
+    pub fn touches(&self, position: Vec2<f32>, radius: f32) -> bool {
+        (self.position - position).len() < self.radius + radius + Self::TOUCH_TOLERANCE
+    }
+
+    /// Returns the pizza the player would hold after using a kitchen thing,
+    /// or `None` if using it does nothing
+    pub fn interaction(&self, typ: KitchenThingType) -> Option<Option<Pizza>> {
+        if self.unemployed_time.is_some() {
+            return None;
+        }
+        match typ {
+            KitchenThingType::Dough => match self.pizza {
+                None => Some(Some(Pizza {
+                    ingredients: BTreeSet::new(),
+                    state: PizzaState::Raw,
+                })),
+                Some(_) => None,
+            },
+            KitchenThingType::IngredientBox(ingredient) => match &self.pizza {
+                Some(pizza)
+                    if pizza.state == PizzaState::Raw
+                        && !pizza.ingredients.contains(&ingredient) =>
+                {
+                    let mut pizza = pizza.clone();
+                    pizza.ingredients.insert(ingredient);
+                    Some(Some(pizza))
+                }
+                _ => None,
+            },
+            KitchenThingType::Oven => match &self.pizza {
+                Some(pizza) if pizza.state == PizzaState::Raw => Some(Some(Pizza {
+                    ingredients: pizza.ingredients.clone(),
+                    state: PizzaState::Cooked,
+                })),
+                _ => None,
+            },
+            KitchenThingType::TrashCan => match self.pizza {
+                Some(_) => Some(None),
+                None => None,
+            },
+        }
+    }
+
+    pub fn can_deliver(&self, order: Option<&Order>) -> bool {
+        if self.unemployed_time.is_some() {
+            return false;
+        }
+        match (&self.pizza, order) {
+            (Some(pizza), Some(order)) => {
+                pizza.state == PizzaState::Cooked && &pizza.ingredients == order
+            }
+            _ => false,
+        }
+    }
+
     pub fn collide(&mut self, position: Vec2<f32>, radius: f32) -> bool {
         let distance = (self.position - position).len();
         if distance > 0.0001 && distance < radius + self.radius {
@@ -116,7 +173,6 @@ pub struct Seat {
     pub position: Vec2<f32>,
     pub leave_position: Vec2<f32>,
     pub radius: f32,
-    pub order: Option<Order>,
     pub color: Color<f32>,
 }
 
@@ -172,133 +228,24 @@ pub struct Boss {
     pub timer: f32,
 }
 
-impl Boss {
-    pub const WALK_SPEED: f32 = 4.0;
-    pub const RUN_SPEED: f32 = 10.0;
-    pub const FIRE_TIMER: f32 = 30.0;
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Model {
     id_gen: IdGen,
     pub boss: Boss,
-    pub ticks_per_second: f64,
+    pub config: Config,
     pub players: HashMap<Id, Player>,
-    pub tables: Vec<Table>,
-    pub seats: Vec<Seat>,
-    pub kitchen: Vec<KitchenThing>,
-    pub pathfind_nodes: Vec<Vec2<f32>>,
-    pub pathfind_edges: Vec<Vec<usize>>,
+    /// Orders placed at each seat, same indices as `level.seats`
+    pub orders: Vec<Option<Order>>,
+    pub level_seed: u64,
+    /// Not serialized, call [Model::load_level] after receiving a model
+    #[serde(skip)]
+    pub level: Arc<Level>,
 }
 
 impl Model {
-    pub const MAX_EMPLOYEES: usize = 5;
-    pub fn new() -> Self {
-        let mut tables = Vec::new();
-        let mut seats = Vec::new();
-        for x in -3..0 {
-            for y in std::array::IntoIter::new([-1, 1]) {
-                let table_pos = vec2(x as f32 * 4.0, y as f32 * 2.0);
-                let table_radius = 1.0;
-                tables.push(Table {
-                    position: table_pos,
-                    radius: table_radius,
-                    color: hsv(global_rng().gen_range(0.0..=1.0), 0.2, 1.0),
-                });
-                const SEATS: usize = 6;
-                for i in 0..SEATS {
-                    seats.push(Seat {
-                        position: table_pos + {
-                            let mut pos = Vec2::rotated(
-                                vec2(table_radius, 0.0),
-                                2.0 * f32::PI * i as f32 / SEATS as f32 + f32::PI / 2.0,
-                            );
-                            pos.x *= 1.1;
-                            pos.y *= 0.8;
-                            pos
-                        },
-                        leave_position: table_pos
-                            + Vec2::rotated(
-                                vec2(table_radius + 0.1 + 0.4 + 0.5 + 0.15, 0.0),
-                                2.0 * f32::PI * i as f32 / SEATS as f32 + f32::PI / 2.0,
-                            ),
-                        radius: 0.3,
-                        order: None,
-                        color: hsv(global_rng().gen_range(0.0..=1.0), 0.2, 0.8),
-                    });
-                }
-            }
-        }
-        let mut kitchen = vec![
-            KitchenThing {
-                typ: KitchenThingType::Dough,
-                position: vec2(1.0, -4.0),
-                radius: 0.8,
-            },
-            KitchenThing {
-                typ: KitchenThingType::TrashCan,
-                position: vec2(3.5, 4.0),
-                radius: 0.7,
-            },
-            KitchenThing {
-                typ: KitchenThingType::Oven,
-                position: vec2(1.0, 4.0),
-                radius: 1.0,
-            },
-        ];
-        {
-            let mut x = -3.0;
-            for ingredient in Ingredient::all() {
-                kitchen.push(KitchenThing {
-                    typ: KitchenThingType::IngredientBox(ingredient),
-                    position: vec2(4.0, x),
-                    radius: 0.7,
-                });
-                x += 1.5;
-            }
-        }
-        let mut pathfind_nodes = Vec::new();
-        let mut pathfind_edges;
-        {
-            const STEP: f32 = 0.5;
-            let mut x = -14.0;
-            while x <= 4.0 {
-                let mut y = -4.0;
-                while y <= 4.0 {
-                    let pos = vec2(x, y);
-                    let mut good = true;
-                    for thing in &kitchen {
-                        if (pos - thing.position).len() < thing.radius + 0.5 {
-                            good = false;
-                        }
-                    }
-                    for seat in &seats {
-                        if (pos - seat.position).len() < seat.radius + 0.5 {
-                            good = false;
-                        }
-                    }
-                    for table in &tables {
-                        if (pos - table.position).len() < table.radius + 0.5 {
-                            good = false;
-                        }
-                    }
-                    if good {
-                        pathfind_nodes.push(pos);
-                    }
-                    y += STEP;
-                }
-                x += STEP;
-            }
-            pathfind_edges = vec![vec![]; pathfind_nodes.len()];
-            for i in 0..pathfind_nodes.len() {
-                for j in 0..pathfind_nodes.len() {
-                    if (pathfind_nodes[i] - pathfind_nodes[j]).len() < STEP * 1.5 {
-                        pathfind_edges[i].push(j);
-                    }
-                }
-            }
-        }
-        let boss_pos = *pathfind_nodes.choose(&mut global_rng()).unwrap();
+    pub fn new(config: Config) -> Self {
+        let level = Level::new(global_rng().gen());
+        let boss_pos = *level.pathfind_nodes.choose(&mut global_rng()).unwrap();
         let boss = Boss {
             timer: 0.0,
             position: boss_pos,
@@ -308,19 +255,50 @@ impl Model {
         let mut model = Self {
             id_gen: IdGen::new(),
             boss,
-            ticks_per_second: 20.0,
+            config,
             players: default(),
-            tables,
-            seats,
-            kitchen,
-            pathfind_nodes,
-            pathfind_edges,
+            orders: vec![None; level.seats.len()],
+            level_seed: level.seed,
+            level: Arc::new(level),
         };
         model
     }
+    /// Fingerprint of everything that only changes through events. Positions are left out,
+    /// clients keep moving players between updates
+    pub fn checksum(&self) -> u64 {
+        let mut players: Vec<&Player> = self.players.values().collect();
+        players.sort_by_key(|player| player.id.raw());
+        let players: Vec<_> = players
+            .into_iter()
+            .map(|player| {
+                (
+                    player.id,
+                    player.score,
+                    &player.pizza,
+                    player.unemployed_time.is_some(),
+                    player.seat,
+                )
+            })
+            .collect();
+        let bytes = bincode::serialize(&(&self.orders, players)).unwrap();
+        // FNV-1a, std's hashers aren't guaranteed to match between builds
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+    /// Generates the level locally after the model was received over the network
+    pub fn load_level(&mut self) {
+        if self.level.seed != self.level_seed || self.level.seats.is_empty() {
+            self.level = Arc::new(Level::new(self.level_seed));
+        }
+    }
     #[must_use]
-    fn spawn_player(&mut self) -> (Id, Vec<Event>) {
-        let player = Player::new(&mut self.id_gen);
+    pub fn spawn_player(&mut self, bot: bool) -> (Id, Vec<Event>) {
+        let mut player = Player::new(&mut self.id_gen);
+        player.bot = bot;
         let events = vec![Event::PlayerJoined(player.clone())];
         let player_id = player.id;
         self.players.insert(player_id, player);
@@ -328,19 +306,37 @@ impl Model {
     }
     #[must_use]
     pub fn welcome(&mut self) -> (WelcomeMessage, Vec<Event>) {
-        let (player_id, events) = self.spawn_player();
+        let (player_id, events) = self.spawn_player(false);
         (
             WelcomeMessage {
                 player_id,
                 model: self.clone(),
+                token: None,
+                next_event: 0,
             },
             events,
         )
     }
+    pub fn welcome_back(&self, player_id: Id) -> WelcomeMessage {
+        WelcomeMessage {
+            player_id,
+            model: self.clone(),
+            token: None,
+            next_event: 0,
+        }
+    }
     #[must_use]
-    pub fn drop_player(&mut self, player_id: Id) -> Vec<Event> {
-        self.players.remove(&player_id);
-        vec![Event::PlayerLeft(player_id)]
+    pub fn drop_player(&mut self, player_id: Id, reason: LeaveReason) -> Vec<Event> {
+        let mut events = Vec::new();
+        if let Some(player) = self.players.remove(&player_id) {
+            if let Some(seat_index) = player.seat {
+                if self.orders[seat_index].take().is_some() {
+                    events.push(Event::Order(seat_index, None));
+                }
+            }
+        }
+        events.push(Event::PlayerLeft(player_id, reason));
+        events
     }
     #[must_use]
     pub fn handle_message(
@@ -351,14 +347,107 @@ impl Model {
     ) -> Vec<Event> {
         let mut events = Vec::new();
         match message {
+            ClientMessage::Event(Event::PlayerUpdated(mut player)) => {
+                // Pizza and score only change through intents
+                if let Some(current) = self.players.get(&player_id) {
+                    player.pizza = current.pizza.clone();
+                    player.score = current.score;
+                    player.bot = current.bot;
+                    // An order leaves together with the customer who made it
+                    if let Some(seat_index) = current.seat {
+                        if player.seat != Some(seat_index) && self.orders[seat_index].is_some() {
+                            self.orders[seat_index] = None;
+                            events.push(Event::Order(seat_index, None));
+                        }
+                    }
+                }
+                let event = Event::PlayerUpdated(player);
+                self.handle_impl(event.clone(), Some(&mut events));
+                events.push(event);
+            }
             ClientMessage::Event(event) => {
                 self.handle_impl(event.clone(), Some(&mut events));
                 events.push(event);
             }
+            ClientMessage::Intent(intent) => {
+                events.extend(self.handle_intent(player_id, intent));
+            }
+            ClientMessage::Hello(_)
+            | ClientMessage::Ping(_)
+            | ClientMessage::QueryServerInfo
+            | ClientMessage::QueryRoom(_)
+            | ClientMessage::Join { .. }
+            | ClientMessage::Leave
+            | ClientMessage::Ack(_)
+            | ClientMessage::Resend(_)
+            | ClientMessage::RequestSnapshot => {}
         }
         events
     }
     #[must_use]
+    pub fn handle_intent(&mut self, player_id: Id, intent: Intent) -> Vec<Event> {
+        match intent {
+            Intent::Interact(thing_index) => self.interact(player_id, thing_index),
+            Intent::Deliver(seat_index) => self.deliver(player_id, seat_index),
+        }
+    }
+    #[must_use]
+    pub fn interact(&mut self, player_id: Id, thing_index: usize) -> Vec<Event> {
+        let thing = match self.level.kitchen.get(thing_index) {
+            Some(thing) => thing,
+            None => return vec![],
+        };
+        let player = match self.players.get_mut(&player_id) {
+            Some(player) => player,
+            None => return vec![],
+        };
+        if !player.touches(thing.position, thing.radius) {
+            return vec![];
+        }
+        match player.interaction(thing.typ) {
+            Some(pizza) => {
+                player.pizza = pizza;
+                vec![
+                    Event::Interacted(thing.typ),
+                    Event::PlayerUpdated(player.clone()),
+                ]
+            }
+            None => vec![],
+        }
+    }
+    /// Hands the cook's pizza to whoever sits at the seat if it is exactly what they ordered
+    #[must_use]
+    pub fn deliver(&mut self, player_id: Id, seat_index: usize) -> Vec<Event> {
+        let seat = match self.level.seats.get(seat_index) {
+            Some(seat) => seat,
+            None => return vec![],
+        };
+        let cook = match self.players.get(&player_id) {
+            Some(player) => player,
+            None => return vec![],
+        };
+        let customer = match self
+            .players
+            .values()
+            .find(|player| player.seat == Some(seat_index) && player.unemployed_time.is_some())
+        {
+            Some(customer) => customer.id,
+            None => return vec![],
+        };
+        if !cook.touches(seat.position, seat.radius)
+            || !cook.can_deliver(self.orders[seat_index].as_ref())
+        {
+            return vec![];
+        }
+        let event = Event::Delivered {
+            cook: player_id,
+            seat: seat_index,
+            customer,
+        };
+        self.handle(event.clone());
+        vec![event]
+    }
+    #[must_use]
     pub fn tick(&mut self) -> Vec<Event> {
         let mut events = Vec::new();
         if self.players.is_empty() {
@@ -372,7 +461,7 @@ impl Model {
                 .map_or(self.boss.position, |player| player.position),
             BossTarget::Walk(pos) => pos,
         });
-        self.boss.timer += 1.0 / self.ticks_per_second as f32;
+        self.boss.timer += 1.0 / self.config.ticks_per_second as f32;
         if boss_node == boss_target_node {
             match self.boss.target {
                 BossTarget::Fire(id) => {
@@ -395,7 +484,8 @@ impl Model {
                         .values()
                         .filter(|player| player.unemployed_time.is_none())
                         .collect();
-                    let max_employees = Model::MAX_EMPLOYEES.min(self.players.len() / 3).max(1);
+                    let max_employees =
+                        self.config.max_employees.min(self.players.len() / 3).max(1);
                     if employees.len() < max_employees {
                         self.boss.target = BossTarget::Hire(
                             self.players
@@ -405,7 +495,7 @@ impl Model {
                                 .unwrap()
                                 .id,
                         );
-                    } else if self.boss.timer > Boss::FIRE_TIMER
+                    } else if self.boss.timer > self.config.fire_timer
                         && !employees.is_empty()
                         && self.players.len() >= 2
                     {
@@ -418,43 +508,21 @@ impl Model {
                         );
                     } else {
                         self.boss.target = BossTarget::Walk(
-                            *self.pathfind_nodes.choose(&mut global_rng()).unwrap(),
+                            *self.level.pathfind_nodes.choose(&mut global_rng()).unwrap(),
                         );
                     }
                 }
             }
         } else {
-            let mut used = vec![false; self.pathfind_nodes.len()];
-            let mut q = std::collections::BinaryHeap::new();
-            let mut d = vec![f32::MAX; self.pathfind_nodes.len()];
-            let mut p = vec![0; self.pathfind_nodes.len()];
-            d[boss_target_node] = 0.0;
-            q.push((r32(0.0), boss_target_node));
-            while let Some((_, v)) = q.pop() {
-                if used[v] {
-                    continue;
-                }
-                if v == boss_node {
-                    break;
-                }
-                used[v] = true;
-                for u in self.pathfind_edges[v].iter().copied() {
-                    let new_d = d[v] + (self.pathfind_nodes[v] - self.pathfind_nodes[u]).len();
-                    if new_d < d[u] {
-                        d[u] = new_d;
-                        p[u] = v;
-                        q.push((r32(-new_d), u));
-                    }
-                }
-            }
-            let next_node = p[boss_node];
-            self.boss.position += (self.pathfind_nodes[next_node] - self.boss.position).clamp(
-                match self.boss.target {
-                    BossTarget::Walk(_) => Boss::WALK_SPEED,
-                    _ => Boss::RUN_SPEED,
-                } * 1.0
-                    / self.ticks_per_second as f32,
-            );
+            let next_node = self.next_node_towards(boss_node, boss_target_node);
+            self.boss.position += (self.level.pathfind_nodes[next_node] - self.boss.position)
+                .clamp(
+                    match self.boss.target {
+                        BossTarget::Walk(_) => self.config.boss_walk_speed,
+                        _ => self.config.boss_run_speed,
+                    } * 1.0
+                        / self.config.ticks_per_second as f32,
+                );
         }
         events.push(Event::BossUpdate(self.boss.clone()));
         events
@@ -468,31 +536,85 @@ impl Model {
                 let player_id = player.id;
                 self.players.insert(player_id, player.clone());
             }
-            Event::PlayerLeft(player_id) => {
+            Event::PlayerLeft(player_id, _) => {
                 self.players.remove(&player_id);
             }
             Event::Order(seat_index, order) => {
-                self.seats[seat_index].order = order;
+                if let Some(seat_order) = self.orders.get_mut(seat_index) {
+                    *seat_order = order;
+                }
             }
             Event::BossUpdate(boss) => {
                 self.boss = boss;
             }
+            Event::ConfigUpdated(config) => {
+                self.config = config;
+            }
+            Event::Delivered { cook, seat, .. } => {
+                if let Some(order) = self.orders.get_mut(seat) {
+                    *order = None;
+                }
+                if let Some(cook) = self.players.get_mut(&cook) {
+                    cook.pizza = None;
+                    cook.score += 1;
+                }
+            }
             Event::Fire(id) => {
                 if let Some(player) = self.players.get_mut(&id) {
                     player.unemployed_time = Some(0.0);
+                    player.pizza = None;
                 }
             }
             Event::Hire(id) => {
                 if let Some(player) = self.players.get_mut(&id) {
                     player.unemployed_time = None;
+                    // Cooks don't sit, and the order goes with them
+                    if let Some(seat_index) = player.seat.take() {
+                        if let Some(seat) = self.level.seats.get(seat_index) {
+                            player.position = seat.leave_position;
+                        }
+                        if let Some(order) = self.orders.get_mut(seat_index) {
+                            *order = None;
+                        }
+                    }
                 }
             }
             _ => {}
         }
     }
 
-    fn find_node(&self, position: Vec2<f32>) -> usize {
-        self.pathfind_nodes
+    /// The next pathfinding node on the shortest way between two nodes
+    pub fn next_node_towards(&self, from: usize, to: usize) -> usize {
+        let mut used = vec![false; self.level.pathfind_nodes.len()];
+        let mut q = std::collections::BinaryHeap::new();
+        let mut d = vec![f32::MAX; self.level.pathfind_nodes.len()];
+        let mut p = vec![0; self.level.pathfind_nodes.len()];
+        d[to] = 0.0;
+        q.push((r32(0.0), to));
+        while let Some((_, v)) = q.pop() {
+            if used[v] {
+                continue;
+            }
+            if v == from {
+                break;
+            }
+            used[v] = true;
+            for u in self.level.pathfind_edges[v].iter().copied() {
+                let new_d =
+                    d[v] + (self.level.pathfind_nodes[v] - self.level.pathfind_nodes[u]).len();
+                if new_d < d[u] {
+                    d[u] = new_d;
+                    p[u] = v;
+                    q.push((r32(-new_d), u));
+                }
+            }
+        }
+        p[from]
+    }
+
+    pub fn find_node(&self, position: Vec2<f32>) -> usize {
+        self.level
+            .pathfind_nodes
             .iter()
             .enumerate()
             .min_by_key(|(_, &pos)| r32((pos - position).len()))
@@ -501,16 +623,190 @@ impl Model {
     }
 }
 
+/// Something a player wants to do, the model decides whether it actually happens
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intent {
+    Interact(usize),
+    Deliver(usize),
+}
+
+/// Why a player is gone, so others can be told
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum LeaveReason {
+    Quit,
+    /// Lost connection and didn't come back in time
+    Timeout,
+    Kicked,
+    /// A bot making room for a human or leaving with the last one, not worth telling anyone
+    Dismissed,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Event {
     Fire(Id),
     Hire(Id),
     Reset,
-    Eat,
+    /// A cook gave a customer the pizza they ordered, clearing the order and scoring a point
+    Delivered {
+        cook: Id,
+        seat: usize,
+        customer: Id,
+    },
     PlayerJoined(Player),
     PlayerUpdated(Player),
-    PlayerLeft(Id),
+    /// Only ever sent by the server, exactly once per player
+    PlayerLeft(Id, LeaveReason),
     BossUpdate(Boss),
     Order(usize, Option<Order>),
     Interacted(KitchenThingType),
+    /// Text from the server admin to show everyone
+    Announcement(String),
+    ConfigUpdated(Config),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kitchen_thing(model: &Model, typ: KitchenThingType) -> usize {
+        model
+            .level
+            .kitchen
+            .iter()
+            .position(|thing| thing.typ == typ)
+            .unwrap()
+    }
+
+    fn hired_player(model: &mut Model, position: Vec2<f32>) -> Id {
+        let (player_id, _) = model.spawn_player(false);
+        model.handle(Event::Hire(player_id));
+        model.players.get_mut(&player_id).unwrap().position = position;
+        player_id
+    }
+
+    /// A customer sitting at the first seat with an order for a cheese pizza
+    fn seated_customer(model: &mut Model) -> Id {
+        let (customer, _) = model.spawn_player(false);
+        let player = model.players.get_mut(&customer).unwrap();
+        player.seat = Some(0);
+        player.position = model.level.seats[0].position;
+        model.orders[0] = Some(vec![Ingredient::Cheese].into_iter().collect());
+        customer
+    }
+
+    fn cheese_pizza(state: PizzaState) -> Option<Pizza> {
+        Some(Pizza {
+            ingredients: vec![Ingredient::Cheese].into_iter().collect(),
+            state,
+        })
+    }
+
+    #[test]
+    fn dough_gives_a_raw_pizza() {
+        let mut model = Model::new(default());
+        let dough = kitchen_thing(&model, KitchenThingType::Dough);
+        let position = model.level.kitchen[dough].position;
+        let cook = hired_player(&mut model, position);
+        let events = model.handle_intent(cook, Intent::Interact(dough));
+        assert!(matches!(
+            events[..],
+            [
+                Event::Interacted(KitchenThingType::Dough),
+                Event::PlayerUpdated(_)
+            ]
+        ));
+        assert!(matches!(
+            &model.players[&cook].pizza,
+            Some(pizza) if pizza.state == PizzaState::Raw && pizza.ingredients.is_empty()
+        ));
+    }
+
+    #[test]
+    fn customers_cant_use_the_kitchen() {
+        let mut model = Model::new(default());
+        let dough = kitchen_thing(&model, KitchenThingType::Dough);
+        let (customer, _) = model.spawn_player(false);
+        model.players.get_mut(&customer).unwrap().position = model.level.kitchen[dough].position;
+        assert!(model
+            .handle_intent(customer, Intent::Interact(dough))
+            .is_empty());
+        assert!(model.players[&customer].pizza.is_none());
+    }
+
+    #[test]
+    fn kitchen_has_to_be_in_reach() {
+        let mut model = Model::new(default());
+        let dough = kitchen_thing(&model, KitchenThingType::Dough);
+        let cook = hired_player(&mut model, vec2(-14.0, 4.0));
+        assert!(model
+            .handle_intent(cook, Intent::Interact(dough))
+            .is_empty());
+        let missing = model.level.kitchen.len();
+        assert!(model
+            .handle_intent(cook, Intent::Interact(missing))
+            .is_empty());
+    }
+
+    #[test]
+    fn delivering_the_order_scores() {
+        let mut model = Model::new(default());
+        let customer = seated_customer(&mut model);
+        let position = model.level.seats[0].position;
+        let cook = hired_player(&mut model, position);
+        model.players.get_mut(&cook).unwrap().pizza = cheese_pizza(PizzaState::Cooked);
+        let events = model.handle_intent(cook, Intent::Deliver(0));
+        match events[..] {
+            [Event::Delivered {
+                cook: event_cook,
+                seat: 0,
+                customer: event_customer,
+            }] => {
+                assert_eq!(event_cook, cook);
+                assert_eq!(event_customer, customer);
+            }
+            _ => panic!("Expected a delivery, got {:?}", events),
+        }
+        assert_eq!(model.players[&cook].score, 1);
+        assert!(model.players[&cook].pizza.is_none());
+        assert!(model.orders[0].is_none());
+    }
+
+    #[test]
+    fn only_the_ordered_pizza_can_be_delivered() {
+        let mut model = Model::new(default());
+        seated_customer(&mut model);
+        let position = model.level.seats[0].position;
+        let cook = hired_player(&mut model, position);
+        model.players.get_mut(&cook).unwrap().pizza = cheese_pizza(PizzaState::Raw);
+        assert!(model.handle_intent(cook, Intent::Deliver(0)).is_empty());
+        let missing = model.level.seats.len();
+        assert!(model
+            .handle_intent(cook, Intent::Deliver(missing))
+            .is_empty());
+        assert_eq!(model.players[&cook].score, 0);
+        assert!(model.orders[0].is_some());
+    }
+
+    #[test]
+    fn hired_customers_leave_their_seat() {
+        let mut model = Model::new(default());
+        let customer = seated_customer(&mut model);
+        model.handle(Event::Hire(customer));
+        assert_eq!(model.players[&customer].seat, None);
+        assert!(model.orders[0].is_none());
+    }
+
+    #[test]
+    fn checksum_ignores_movement() {
+        let mut model = Model::new(default());
+        let cook = hired_player(&mut model, vec2(0.0, 0.0));
+        let checksum = model.checksum();
+        model.players.get_mut(&cook).unwrap().position = vec2(1.0, 1.0);
+        assert_eq!(model.checksum(), checksum);
+        model.handle(Event::Order(
+            0,
+            Some(vec![Ingredient::Tomato].into_iter().collect()),
+        ));
+        assert_ne!(model.checksum(), checksum);
+    }
 }
@@ -1,24 +1,310 @@
 use super::*;
 
+/// Bump this whenever [ClientMessage] or [ServerMessage] (or anything inside them) changes
+pub const PROTOCOL_VERSION: u32 = 15;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
+    /// Has to be sent first, the server ignores everything else until the versions match.
+    /// Must stay the first variant so that any client version can decode it
+    Hello(u32),
+    /// Ask for a [ServerInfo] without joining anything
+    QueryServerInfo,
+    /// Ask about one room by its code, private ones included, answered with [ServerMessage::RoomInfo]
+    QueryRoom(String),
+    /// Join a room by its code, has to be sent before any events or intents.
+    /// A token from an earlier welcome reclaims the same player if the server still holds it.
+    /// `public` lists the room in [ServerInfo], only counts for whoever creates the room
+    Join {
+        room: String,
+        public: bool,
+        token: Option<SessionToken>,
+        name: String,
+    },
     Event(Event),
+    Intent(Intent),
+    /// Keeps the connection alive, the server answers with a [ServerMessage::Pong] carrying the same number
+    Ping(u64),
+    /// The player quits for good, the server removes them right away instead of waiting for a reconnect
+    Leave,
+    /// Every event before this index has been applied, so the server doesn't need to keep them
+    Ack(usize),
+    /// Events from this index on never arrived, the server sends them again if it still has them
+    /// and a snapshot otherwise
+    Resend(usize),
+    /// The client's model went wrong somehow and it needs the whole model again
+    RequestSnapshot,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken(u64);
+
+impl SessionToken {
+    pub fn new() -> Self {
+        Self(global_rng().gen())
+    }
 }
 
+/// The model in here comes without its level, see [Model::load_level]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WelcomeMessage {
     pub player_id: Id,
     pub model: Model,
+    pub token: Option<SessionToken>,
+    /// Index of the first event that is not in the model yet
+    pub next_event: usize,
+}
+
+pub const PUBLIC_ROOM: &str = "public";
+pub const MAX_ROOM_CODE_LEN: usize = 16;
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Keeps letters, digits and spaces of a player name, cut to [MAX_NAME_LEN]
+pub fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == ' ')
+        .take(MAX_NAME_LEN)
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// Turns whatever the player typed into a room name, empty codes go to the public room
+pub fn normalize_room_code(code: &str) -> String {
+    let code = code
+        .trim()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(MAX_ROOM_CODE_LEN)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if code.is_empty() {
+        PUBLIC_ROOM.to_owned()
+    } else {
+        code
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    pub employees: usize,
+    /// How many of the players are bots
+    pub bots: usize,
+    /// How far behind schedule the room's last tick finished, in seconds
+    pub tick_lag: f32,
+}
+
+impl RoomInfo {
+    pub fn customers(&self) -> usize {
+        self.players - self.employees
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServerInfo {
+    pub rooms: Vec<RoomInfo>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessage {
+    /// The answer to [ClientMessage::Hello] with the server's protocol version.
+    /// Must stay the first variant so that any client version can decode it
+    Hello(u32),
+    ServerInfo(ServerInfo),
+    /// `None` when there is no room with that code
+    RoomInfo(Option<RoomInfo>),
     Welcome(WelcomeMessage),
-    Update(Vec<Event>),
+    /// Events numbered from `first` on, each update continues right where the previous one ended
+    Update {
+        first: usize,
+        events: Vec<Event>,
+    },
+    /// Replaces the whole model, sent when the client fell too far behind or asked for it
+    Snapshot {
+        model: Model,
+        next_event: usize,
+    },
+    /// [Model::checksum] after applying every event before `next_event`,
+    /// a client that gets a different one should ask for a snapshot
+    Checksum {
+        next_event: usize,
+        checksum: u64,
+    },
+    Pong(u64),
+    /// Sent right before the server drops the player, with the reason to show
+    Kicked(String),
+}
+
+impl ServerMessage {
+    /// Numbers the events the way the server does, for games without one
+    pub fn local_update(next_event: &mut usize, events: Vec<Event>) -> Self {
+        let first = *next_event;
+        *next_event += events.len();
+        Self::Update { first, events }
+    }
+}
+
+/// The client's side of event numbering: what has been applied and what to tell the server about it
+pub struct EventSequence {
+    next_event: usize,
+    /// What we last told the server we have applied
+    acked_event: usize,
+    /// Asked for the events after a gap, updates until they arrive are useless
+    resend_requested: bool,
+}
+
+impl EventSequence {
+    pub fn new(next_event: usize) -> Self {
+        Self {
+            next_event,
+            acked_event: next_event,
+            resend_requested: false,
+        }
+    }
+
+    /// Index of the next event we expect from the server
+    pub fn next_event(&self) -> usize {
+        self.next_event
+    }
+
+    /// Returns the events of an update that haven't been applied yet. After a gap nothing is
+    /// returned until the missing events arrive, a request for them is added to `to_send` once
+    pub fn receive(
+        &mut self,
+        first: usize,
+        events: Vec<Event>,
+        to_send: &mut Vec<ClientMessage>,
+    ) -> Vec<Event> {
+        if first > self.next_event {
+            if !self.resend_requested {
+                self.resend_requested = true;
+                to_send.push(ClientMessage::Resend(self.next_event));
+            }
+            return vec![];
+        }
+        self.resend_requested = false;
+        let already_applied = self.next_event - first;
+        self.next_event = self.next_event.max(first + events.len());
+        events.into_iter().skip(already_applied).collect()
+    }
+
+    /// A snapshot contains every event before `next_event`
+    pub fn snapshot(&mut self, next_event: usize) {
+        self.next_event = next_event;
+        self.resend_requested = false;
+    }
+
+    /// Returns an [ClientMessage::Ack] if anything was applied since the last one
+    pub fn ack(&mut self) -> Option<ClientMessage> {
+        if self.acked_event == self.next_event {
+            return None;
+        }
+        self.acked_event = self.next_event;
+        Some(ClientMessage::Ack(self.next_event))
+    }
+}
+
+/// Intents are resolved by the server, this keeps a client from repeating one while it waits
+#[derive(Default)]
+pub struct IntentCooldown {
+    sent: HashMap<Intent, f32>,
+}
+
+impl IntentCooldown {
+    pub const TIME: f32 = 0.5;
+
+    /// Whether `intent` can be sent at time `t`, if so it counts as sent
+    pub fn try_send(&mut self, intent: Intent, t: f32) -> bool {
+        let last_time = self.sent.get(&intent).copied().unwrap_or(-100.0);
+        if last_time < t - Self::TIME {
+            self.sent.insert(intent, t);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// When a client sends its regular messages: player updates, pings and acks
+#[derive(Default)]
+pub struct ClientTimers {
+    next_player_update: f32,
+    next_ping: f32,
+    next_ack: f32,
+    /// The id and send time of the ping we are waiting an answer for
+    ping_sent: Option<(u64, f32)>,
+}
+
+impl ClientTimers {
+    pub const PLAYER_UPDATE_INTERVAL: f32 = 0.05;
+    pub const PING_INTERVAL: f32 = 1.0;
+    pub const ACK_INTERVAL: f32 = 0.25;
+
+    /// Starts somewhere inside every interval, so that many clients don't all send at once
+    pub fn staggered() -> Self {
+        Self {
+            next_player_update: global_rng().gen_range(0.0..=Self::PLAYER_UPDATE_INTERVAL),
+            next_ping: global_rng().gen_range(0.0..=Self::PING_INTERVAL),
+            next_ack: global_rng().gen_range(0.0..=Self::ACK_INTERVAL),
+            ping_sent: None,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.next_player_update -= delta_time;
+        self.next_ping -= delta_time;
+        self.next_ack -= delta_time;
+    }
+
+    /// Whether it's time to send our player again
+    pub fn player_update(&mut self) -> bool {
+        if self.next_player_update > 0.0 {
+            return false;
+        }
+        self.next_player_update = Self::PLAYER_UPDATE_INTERVAL;
+        true
+    }
+
+    /// Returns a [ClientMessage::Ping] if it's time for one, `t` is kept to measure the latency
+    pub fn ping(&mut self, t: f32) -> Option<ClientMessage> {
+        if self.next_ping > 0.0 {
+            return None;
+        }
+        self.next_ping = Self::PING_INTERVAL;
+        let id = self.ping_sent.map_or(0, |(id, _)| id + 1);
+        self.ping_sent = Some((id, t));
+        Some(ClientMessage::Ping(id))
+    }
+
+    /// Round trip time if `id` answers our last ping
+    pub fn pong(&self, id: u64, t: f32) -> Option<f32> {
+        match self.ping_sent {
+            Some((sent_id, sent_time)) if sent_id == id => Some(t - sent_time),
+            _ => None,
+        }
+    }
+
+    /// Returns an [ClientMessage::Ack] if it's time for one and anything was applied since the last
+    pub fn ack(&mut self, event_sequence: &mut EventSequence) -> Option<ClientMessage> {
+        if self.next_ack > 0.0 {
+            return None;
+        }
+        let ack = event_sequence.ack()?;
+        self.next_ack = Self::ACK_INTERVAL;
+        Some(ack)
+    }
 }
 
 pub enum Connection {
-    Local { next_tick: f64, model: Model },
+    /// Offline practice, the model runs right in the client along with its bots
+    Local {
+        next_tick: f64,
+        model: Model,
+        bots: Vec<Bot>,
+        next_event: usize,
+    },
     Remote(geng::net::client::Connection<ServerMessage, ClientMessage>),
 }
 
@@ -30,3 +316,127 @@ impl Connection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_applied_once() {
+        let mut sequence = EventSequence::new(5);
+        let mut to_send = Vec::new();
+        assert_eq!(
+            sequence
+                .receive(5, vec![Event::Reset; 2], &mut to_send)
+                .len(),
+            2
+        );
+        // Overlaps with what we already have, only the last one is new
+        assert_eq!(
+            sequence
+                .receive(6, vec![Event::Reset; 2], &mut to_send)
+                .len(),
+            1
+        );
+        assert_eq!(
+            sequence
+                .receive(4, vec![Event::Reset; 3], &mut to_send)
+                .len(),
+            0
+        );
+        assert_eq!(sequence.next_event(), 8);
+        assert!(to_send.is_empty());
+    }
+
+    #[test]
+    fn gap_asks_for_a_resend_once() {
+        let mut sequence = EventSequence::new(5);
+        let mut to_send = Vec::new();
+        assert!(sequence
+            .receive(7, vec![Event::Reset; 1], &mut to_send)
+            .is_empty());
+        assert!(sequence
+            .receive(8, vec![Event::Reset; 1], &mut to_send)
+            .is_empty());
+        assert!(matches!(to_send[..], [ClientMessage::Resend(5)]));
+        assert_eq!(sequence.next_event(), 5);
+
+        to_send.clear();
+        assert_eq!(
+            sequence
+                .receive(5, vec![Event::Reset; 4], &mut to_send)
+                .len(),
+            4
+        );
+        assert_eq!(sequence.next_event(), 9);
+        // Another gap later is reported again
+        sequence.receive(10, vec![Event::Reset; 1], &mut to_send);
+        assert!(matches!(to_send[..], [ClientMessage::Resend(9)]));
+    }
+
+    #[test]
+    fn snapshot_fills_the_gap() {
+        let mut sequence = EventSequence::new(5);
+        let mut to_send = Vec::new();
+        sequence.receive(7, vec![Event::Reset; 1], &mut to_send);
+        sequence.snapshot(8);
+        assert_eq!(sequence.next_event(), 8);
+        assert_eq!(
+            sequence
+                .receive(8, vec![Event::Reset; 1], &mut to_send)
+                .len(),
+            1
+        );
+        assert_eq!(to_send.len(), 1);
+    }
+
+    #[test]
+    fn acks_only_progress() {
+        let mut sequence = EventSequence::new(5);
+        let mut to_send = Vec::new();
+        assert!(sequence.ack().is_none());
+        sequence.receive(5, vec![Event::Reset; 3], &mut to_send);
+        assert!(matches!(sequence.ack(), Some(ClientMessage::Ack(8))));
+        assert!(sequence.ack().is_none());
+        sequence.snapshot(20);
+        assert!(matches!(sequence.ack(), Some(ClientMessage::Ack(20))));
+    }
+
+    #[test]
+    fn intents_cool_down_separately() {
+        let mut cooldown = IntentCooldown::default();
+        assert!(cooldown.try_send(Intent::Interact(0), 0.0));
+        assert!(!cooldown.try_send(Intent::Interact(0), 0.1));
+        assert!(cooldown.try_send(Intent::Interact(1), 0.1));
+        assert!(cooldown.try_send(Intent::Interact(0), IntentCooldown::TIME + 0.1));
+    }
+
+    #[test]
+    fn timers_send_on_their_intervals() {
+        let mut timers = ClientTimers::default();
+        let mut sequence = EventSequence::new(0);
+        let mut to_send = Vec::new();
+        assert!(timers.player_update());
+        assert!(!timers.player_update());
+        assert!(matches!(timers.ping(0.0), Some(ClientMessage::Ping(0))));
+        assert!(timers.ping(0.0).is_none());
+        // Nothing to ack doesn't use up the turn
+        assert!(timers.ack(&mut sequence).is_none());
+        sequence.receive(0, vec![Event::Reset; 2], &mut to_send);
+        assert!(matches!(
+            timers.ack(&mut sequence),
+            Some(ClientMessage::Ack(2))
+        ));
+
+        timers.update(ClientTimers::ACK_INTERVAL);
+        sequence.receive(2, vec![Event::Reset; 1], &mut to_send);
+        assert!(matches!(
+            timers.ack(&mut sequence),
+            Some(ClientMessage::Ack(3))
+        ));
+        assert!(timers.player_update());
+        assert!(timers.ping(0.25).is_none());
+        assert_eq!(timers.pong(0, 0.5), Some(0.5));
+        assert_eq!(timers.pong(1, 0.5), None);
+    }
+}
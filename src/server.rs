@@ -1,154 +1,881 @@
 use super::*;
 
+mod admin;
+mod bans;
+mod tls;
+
+use bans::BanList;
+
+/// A joined client as seen by the room's tick loop
+struct ClientConnection {
+    /// Tells this connection apart from a newer one that reclaimed the same player
+    id: usize,
+    sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    /// First event not sent yet
+    next_event: usize,
+    /// First event the client hasn't confirmed applying, older ones can be forgotten
+    acked_event: usize,
+    snapshot_requested: bool,
+    /// Where the last resync snapshot put the client, nothing else is sent until it confirms that
+    unconfirmed_snapshot: Option<usize>,
+    last_checksum: std::time::Instant,
+    last_seen: std::time::Instant,
+}
+
 struct ServerState {
     model: Model,
-    events: std::collections::VecDeque<Event>,
+    /// Sent events that not every client has confirmed yet, with when they happened
+    events: std::collections::VecDeque<(std::time::Instant, Event)>,
     next_event_index: usize,
     first_event_index: usize,
-    clients_next_event: HashMap<Id, usize>,
+    clients: HashMap<Id, ClientConnection>,
+    next_connection_id: usize,
+    sessions: HashMap<SessionToken, Id>,
+    disconnected: HashMap<Id, std::time::Instant>,
+    /// How far behind schedule the last tick finished
+    tick_lag: std::time::Duration,
+    bots: Vec<Bot>,
+    /// Bots fill the room up to this many players while there is anyone to play with
+    min_players: usize,
 }
 
 impl ServerState {
-    fn new(model: Model) -> Self {
+    /// How long a player whose connection dropped is kept around waiting for a reconnect
+    const RECONNECT_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(60);
+    /// Clients confirm events several times a second, one that hasn't confirmed anything
+    /// for this long is stalled and gets a snapshot instead of the events it missed.
+    /// Counted in time rather than events, a busy room goes through a lot of them
+    const BACKLOG_TIME: std::time::Duration = std::time::Duration::from_secs(5);
+    const CHECKSUM_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+    /// Clients ping every second, one that stays silent this long is treated as disconnected
+    const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+    fn new(model: Model, min_players: usize) -> Self {
         Self {
             model,
             events: default(),
             next_event_index: 0,
             first_event_index: 0,
-            clients_next_event: default(),
+            clients: default(),
+            next_connection_id: 0,
+            sessions: default(),
+            disconnected: default(),
+            tick_lag: std::time::Duration::from_secs(0),
+            bots: Vec::new(),
+            min_players,
+        }
+    }
+    fn humans(&self) -> usize {
+        self.model.players.len().saturating_sub(self.bots.len())
+    }
+    /// Adds or removes a bot if the room is under or over populated, then lets the bots play
+    fn update_bots(&mut self, delta_time: f32) {
+        let humans = self.humans();
+        if humans == 0 {
+            for bot in mem::take(&mut self.bots) {
+                let events = self
+                    .model
+                    .drop_player(bot.player_id(), LeaveReason::Dismissed);
+                self.add_events(events);
+            }
+        } else if self.model.players.len() < self.min_players {
+            let (bot, events) = Bot::spawn(&mut self.model);
+            self.bots.push(bot);
+            self.add_events(events);
+        } else if self.model.players.len() > self.min_players {
+            if let Some(bot) = self.bots.pop() {
+                let events = self
+                    .model
+                    .drop_player(bot.player_id(), LeaveReason::Dismissed);
+                self.add_events(events);
+            }
+        }
+        for index in 0..self.bots.len() {
+            let messages = self.bots[index].update(&self.model, delta_time);
+            let player_id = self.bots[index].player_id();
+            for message in messages {
+                let events = self.model.handle_message(player_id, message);
+                self.add_events(events);
+            }
+        }
+    }
+    fn is_connected(&self, player_id: Id, connection_id: usize) -> bool {
+        self.clients.get(&player_id).map(|client| client.id) == Some(connection_id)
+    }
+    fn seen(&mut self, player_id: Id) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.last_seen = std::time::Instant::now();
+        }
+    }
+    /// Does nothing if the player has already been reclaimed by another connection
+    fn disconnect(&mut self, player_id: Id, connection_id: usize) {
+        if !self.is_connected(player_id, connection_id) {
+            return;
+        }
+        self.clients.remove(&player_id);
+        if self.model.players.contains_key(&player_id) {
+            self.disconnected
+                .insert(player_id, std::time::Instant::now());
+        } else {
+            self.sessions.retain(|_, id| *id != player_id);
+        }
+    }
+    /// Hands the player over to a new connection. The old one may not have been noticed
+    /// as gone yet, then it is dropped and whatever it still sends is ignored
+    fn reconnect(&mut self, token: SessionToken) -> Option<Id> {
+        let player_id = *self.sessions.get(&token)?;
+        if self.disconnected.remove(&player_id).is_none()
+            && self.clients.remove(&player_id).is_none()
+        {
+            return None;
+        }
+        Some(player_id)
+    }
+    /// Removes the player right away, without waiting for a reconnect
+    fn kick(&mut self, player_id: Id, reason: &str) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.sender.send(ServerMessage::Kicked(reason.to_owned()));
+        }
+        self.remove_player(player_id, LeaveReason::Kicked);
+    }
+    /// The one place players leave the room, so everyone hears about it exactly once
+    fn remove_player(&mut self, player_id: Id, reason: LeaveReason) {
+        self.clients.remove(&player_id);
+        self.disconnected.remove(&player_id);
+        self.sessions.retain(|_, id| *id != player_id);
+        self.bots.retain(|bot| bot.player_id() != player_id);
+        if self.model.players.contains_key(&player_id) {
+            let events = self.model.drop_player(player_id, reason);
+            self.add_events(events);
+        }
+    }
+    fn drop_idle_clients(&mut self) {
+        let idle: Vec<(Id, usize)> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.last_seen.elapsed() > Self::IDLE_TIMEOUT)
+            .map(|(&player_id, client)| (player_id, client.id))
+            .collect();
+        for (player_id, connection_id) in idle {
+            self.disconnect(player_id, connection_id);
+        }
+    }
+    fn drop_expired_sessions(&mut self) {
+        let expired: Vec<Id> = self
+            .disconnected
+            .iter()
+            .filter(|(_, time)| time.elapsed() > Self::RECONNECT_GRACE_PERIOD)
+            .map(|(&player_id, _)| player_id)
+            .collect();
+        for player_id in expired {
+            self.remove_player(player_id, LeaveReason::Timeout);
         }
     }
     fn add_events(&mut self, events: impl IntoIterator<Item = Event>) {
+        let now = std::time::Instant::now();
         for event in events.into_iter() {
             // eprintln!("Add {}: {:?}", self.next_event_index, event);
-            self.events.push_back(event);
+            self.events.push_back((now, event));
             self.next_event_index += 1;
         }
+        self.shrink();
     }
     fn shrink(&mut self) {
         let next_needed_event_index = self
-            .clients_next_event
+            .clients
             .values()
-            .map(|&index| index)
+            .map(|client| {
+                client
+                    .acked_event
+                    .max(client.unconfirmed_snapshot.unwrap_or(0))
+            })
             .min()
             .unwrap_or(self.next_event_index);
-        while self.first_event_index < next_needed_event_index {
+        while let Some((time, _)) = self.events.front() {
+            // Clients still waiting for older events get a snapshot instead
+            if self.first_event_index >= next_needed_event_index
+                && time.elapsed() < Self::BACKLOG_TIME
+            {
+                break;
+            }
             self.events.pop_front();
             self.first_event_index += 1;
         }
     }
-    fn get_new_events(&mut self, player_id: Id) -> Vec<Event> {
-        let next_event_index = self.clients_next_event[&player_id];
-        let mut result = Vec::new();
-        for index in next_event_index..self.next_event_index {
-            result.push(self.events[index - self.first_event_index].clone());
+    /// Returns the id of the new connection
+    fn add_client(
+        &mut self,
+        player_id: Id,
+        sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    ) -> usize {
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+        self.clients.insert(
+            player_id,
+            ClientConnection {
+                id,
+                sender,
+                next_event: self.next_event_index,
+                acked_event: self.next_event_index,
+                snapshot_requested: false,
+                unconfirmed_snapshot: None,
+                last_checksum: std::time::Instant::now(),
+                last_seen: std::time::Instant::now(),
+            },
+        );
+        id
+    }
+    fn ack(&mut self, player_id: Id, next_event: usize) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            // Can't confirm events it was never sent
+            client.acked_event = next_event.clamp(client.acked_event, client.next_event);
+        }
+    }
+    fn resend(&mut self, player_id: Id, from: usize) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            // A pending snapshot is going to cover the gap anyway
+            if client.unconfirmed_snapshot.is_none() {
+                // Everything before the gap has been applied, anything after is sent
+                // again from the backlog, or a snapshot once it's not there anymore
+                client.acked_event = from.clamp(client.acked_event, client.next_event);
+                client.next_event = client.acked_event;
+            }
+        }
+    }
+    /// Sends every client the events it hasn't seen yet, and a checksum every once in a while
+    /// so that a client that applied something differently can ask for a snapshot.
+    /// A client that hasn't confirmed anything in the whole backlog gets a snapshot and then
+    /// nothing until it confirms it, so a stalled client can't pile up messages in its connection
+    fn send_updates(&mut self) {
+        let model = &self.model;
+        let mut checksum = None;
+        for client in self.clients.values_mut() {
+            if let Some(snapshot_event) = client.unconfirmed_snapshot {
+                if client.acked_event < snapshot_event {
+                    continue;
+                }
+                client.unconfirmed_snapshot = None;
+            }
+            let resync = client.snapshot_requested || client.acked_event < self.first_event_index;
+            if resync {
+                client.sender.send(ServerMessage::Snapshot {
+                    model: model.clone(),
+                    next_event: self.next_event_index,
+                });
+                client.snapshot_requested = false;
+                client.unconfirmed_snapshot = Some(self.next_event_index);
+            } else {
+                let events = self
+                    .events
+                    .range(client.next_event - self.first_event_index..)
+                    .map(|(_, event)| event.clone())
+                    .collect();
+                client.sender.send(ServerMessage::Update {
+                    first: client.next_event,
+                    events,
+                });
+                if client.last_checksum.elapsed() > Self::CHECKSUM_INTERVAL {
+                    client.sender.send(ServerMessage::Checksum {
+                        next_event: self.next_event_index,
+                        checksum: *checksum.get_or_insert_with(|| model.checksum()),
+                    });
+                    client.last_checksum = std::time::Instant::now();
+                }
+            }
+            client.next_event = self.next_event_index;
         }
-        self.clients_next_event
-            .insert(player_id, self.next_event_index);
         self.shrink();
-        // eprintln!("{:?}: {:?}", player_id, result);
-        result
     }
 }
 
-struct Client {
+/// A restaurant with its own model, event queue and tick loop
+struct Room {
+    name: String,
+    /// Listed in [ServerInfo], private rooms can only be joined by code
+    public: bool,
+    state: Arc<Mutex<ServerState>>,
+    running: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl Room {
+    const MAX_CATCH_UP_TICKS: usize = 5;
+    /// Falling behind under load tends to happen every tick, so only report it this often
+    const LAG_WARNING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+    fn new(name: String, public: bool, config: &ServerConfig) -> Self {
+        let state = Arc::new(Mutex::new(ServerState::new(
+            Model::new(config.game.clone()),
+            config.min_players,
+        )));
+        let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        std::thread::spawn({
+            let name = name.clone();
+            let state = state.clone();
+            let running = running.clone();
+            move || Self::tick_loop(&name, &state, &running)
+        });
+        Self {
+            name,
+            public,
+            state,
+            running,
+        }
+    }
+
+    /// Runs model ticks at a fixed rate, catching up on ticks missed because of
+    /// lock contention or slow ticks so that the game runs at the same speed under load
+    fn tick_loop(name: &str, state: &Mutex<ServerState>, running: &std::sync::atomic::AtomicBool) {
+        let mut next_tick = std::time::Instant::now();
+        let mut last_lag_warning: Option<std::time::Instant> = None;
+        let mut times_behind = 0;
+        while running.load(std::sync::atomic::Ordering::Relaxed) {
+            let now = std::time::Instant::now();
+            if next_tick > now {
+                std::thread::sleep(next_tick - now);
+                continue;
+            }
+            let mut state = state.lock().unwrap();
+            let tick_duration =
+                std::time::Duration::from_secs_f64(1.0 / state.model.config.ticks_per_second);
+            let mut ticks = 0;
+            while next_tick <= std::time::Instant::now() && ticks < Self::MAX_CATCH_UP_TICKS {
+                state.drop_idle_clients();
+                state.drop_expired_sessions();
+                state.update_bots(tick_duration.as_secs_f32());
+                let events = state.model.tick();
+                state.add_events(events);
+                next_tick += tick_duration;
+                ticks += 1;
+            }
+            state.send_updates();
+            let now = std::time::Instant::now();
+            state.tick_lag = now.saturating_duration_since(next_tick);
+            if next_tick < now {
+                times_behind += 1;
+                if last_lag_warning.map_or(true, |time| time.elapsed() > Self::LAG_WARNING_INTERVAL)
+                {
+                    warn!(
+                        "Room {:?} is falling behind by {:.0}ms, skipped ticks {} times since the last warning",
+                        name,
+                        state.tick_lag.as_secs_f64() * 1000.0,
+                        times_behind,
+                    );
+                    last_lag_warning = Some(now);
+                    times_behind = 0;
+                }
+                next_tick = now;
+            }
+        }
+    }
+
+    /// Bots don't count, they leave once the last human does
+    fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().humans() == 0
+    }
+
+    fn info(&self) -> RoomInfo {
+        let state = self.state.lock().unwrap();
+        RoomInfo {
+            name: self.name.clone(),
+            players: state.model.players.len(),
+            employees: state
+                .model
+                .players
+                .values()
+                .filter(|player| player.unemployed_time.is_none())
+                .count(),
+            bots: state.bots.len(),
+            tick_lag: state.tick_lag.as_secs_f32(),
+        }
+    }
+}
+
+impl Drop for Room {
+    fn drop(&mut self) {
+        self.running
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+type Rooms = Arc<Mutex<HashMap<String, Arc<Room>>>>;
+type Bans = Arc<Mutex<BanList>>;
+
+/// Extra room given to movement checks so that network jitter doesn't rubber-band honest players.
+const MOVEMENT_TOLERANCE: f32 = 1.5;
+const POSITION_TOLERANCE: f32 = 0.5;
+
+/// A client that has joined a room
+struct Session {
+    room: Arc<Room>,
     player_id: Id,
-    server_state: Arc<Mutex<ServerState>>,
-    sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    connection_id: usize,
+    last_update: std::time::Instant,
+}
+
+impl Session {
+    /// `delta_time` is how long it has been since the previous update was accepted.
+    /// Returns `None` for an update with NaN or infinite numbers, no clamping can fix those
+    fn validate_player_update(
+        model: &Model,
+        previous: &Player,
+        mut player: Player,
+        delta_time: f32,
+    ) -> Option<Player> {
+        let numbers = [
+            player.radius,
+            player.position.x,
+            player.position.y,
+            player.velocity.x,
+            player.velocity.y,
+            player.target_velocity.x,
+            player.target_velocity.y,
+            player.unemployed_time.unwrap_or(0.0),
+            player.t,
+            player.color.r,
+            player.color.g,
+            player.color.b,
+            player.color.a,
+        ];
+        if !numbers.iter().all(|number| number.is_finite()) {
+            return None;
+        }
+
+        player.id = previous.id;
+        player.radius = previous.radius;
+        player.name = normalize_name(&player.name);
+        player.target_velocity = player.target_velocity.clamp(1.0);
+        player.velocity = player.velocity.clamp(model.config.player_speed);
+
+        // Employment only changes through Hire/Fire, the unemployed timer can't run faster than real time
+        player.unemployed_time = previous.unemployed_time.map(|time| {
+            player
+                .unemployed_time
+                .unwrap_or(time)
+                .min(time + delta_time * MOVEMENT_TOLERANCE)
+                .max(0.0)
+        });
+
+        let from = match previous.seat {
+            Some(seat_index) if player.seat != previous.seat => {
+                model.level.seats[seat_index].leave_position
+            }
+            _ => previous.position,
+        };
+        let max_distance =
+            model.config.player_speed * delta_time * MOVEMENT_TOLERANCE + POSITION_TOLERANCE;
+        player.position = from + (player.position - from).clamp(max_distance);
+        player.position.x = clamp(player.position.x, -14.0..=4.0);
+        player.position.y = clamp(player.position.y, -4.0..=4.0);
+
+        if let Some(seat_index) = player.seat {
+            let valid = match model.level.seats.get(seat_index) {
+                Some(seat) => {
+                    previous.seat == Some(seat_index)
+                        || (player.unemployed_time.is_some()
+                            && player.touches(seat.position, seat.radius)
+                            && !model.players.values().any(|other| {
+                                other.id != player.id && other.seat == Some(seat_index)
+                            }))
+                }
+                None => false,
+            };
+            if valid {
+                player.position = model.level.seats[seat_index].position;
+                player.velocity = vec2(0.0, 0.0);
+            } else {
+                player.seat = None;
+            }
+        }
+
+        Some(player)
+    }
+
+    /// Checks a message from the client, returning a corrected version or `None` if it has to be dropped
+    fn validate(&mut self, model: &Model, message: ClientMessage) -> Option<ClientMessage> {
+        let previous = model.players.get(&self.player_id)?;
+        match message {
+            ClientMessage::Event(Event::PlayerUpdated(player)) => {
+                let delta_time = self.last_update.elapsed().as_secs_f32();
+                self.last_update = std::time::Instant::now();
+                Self::validate_player_update(model, previous, player, delta_time)
+                    .map(|player| ClientMessage::Event(Event::PlayerUpdated(player)))
+            }
+            // Orders are cleared by the server on delivery or when the customer leaves
+            ClientMessage::Event(Event::Order(seat_index, Some(order))) => {
+                let valid = previous.unemployed_time.is_some()
+                    && previous.seat == Some(seat_index)
+                    && model.orders.get(seat_index) == Some(&None)
+                    && !order.is_empty();
+                if valid {
+                    Some(ClientMessage::Event(Event::Order(seat_index, Some(order))))
+                } else {
+                    None
+                }
+            }
+            ClientMessage::Event(_)
+            | ClientMessage::Hello(_)
+            | ClientMessage::Ping(_)
+            | ClientMessage::QueryServerInfo
+            | ClientMessage::QueryRoom(_)
+            | ClientMessage::Join { .. }
+            | ClientMessage::Leave
+            | ClientMessage::Ack(_)
+            | ClientMessage::Resend(_)
+            | ClientMessage::RequestSnapshot => None,
+            ClientMessage::Intent(intent) => Some(ClientMessage::Intent(intent)),
+        }
+    }
+}
+
+/// Token bucket for incoming messages, with a tally of rejected ones that slowly cools down
+struct RateLimiter {
+    tokens: f32,
+    strikes: f32,
+    last_refill: std::time::Instant,
+}
+
+impl RateLimiter {
+    /// A normal client sends 20 player updates, a ping and 4 acks per second,
+    /// plus an intent every now and then
+    const RATE: f32 = 40.0;
+    const BURST: f32 = 80.0;
+    const MAX_STRIKES: f32 = 100.0;
+
+    fn new() -> Self {
+        Self {
+            tokens: Self::BURST,
+            strikes: 0.0,
+            last_refill: std::time::Instant::now(),
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        let delta_time = self.last_refill.elapsed().as_secs_f32();
+        self.last_refill = std::time::Instant::now();
+        self.tokens = (self.tokens + delta_time * Self::RATE).min(Self::BURST);
+        self.strikes = (self.strikes - delta_time).max(0.0);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns whether the client has misbehaved enough to be disconnected
+    fn strike(&mut self) -> bool {
+        self.strikes += 1.0;
+        self.strikes > Self::MAX_STRIKES
+    }
+}
+
+/// Measured by the size of the message encoded again, this only keeps big messages away
+/// from the model and the other players.
+///
+/// It is not a limit on what a client can send: geng reads and decodes whole websocket
+/// messages before handing them to [Client], and has no setting for their size,
+/// so a huge message still costs the memory and time to decode it
+const MAX_MESSAGE_SIZE: u64 = 4096;
+
+struct Client {
+    rooms: Rooms,
+    bans: Bans,
+    config: Arc<ServerConfig>,
+    /// Handed over to the room once joined
+    sender: Option<Box<dyn geng::net::Sender<ServerMessage>>>,
+    session: Option<Session>,
+    /// Whether the client said hello with a matching protocol version
+    compatible: bool,
+    rate_limiter: RateLimiter,
+    /// Everything is ignored after a kick
+    kicked: bool,
+}
+
+impl Client {
+    fn kick(&mut self, reason: &str) {
+        self.kicked = true;
+        match &self.session {
+            Some(session) => {
+                let mut server_state = session.room.state.lock().unwrap();
+                if server_state.is_connected(session.player_id, session.connection_id) {
+                    server_state.kick(session.player_id, reason);
+                }
+            }
+            None => {
+                if let Some(sender) = &mut self.sender {
+                    sender.send(ServerMessage::Kicked(reason.to_owned()));
+                }
+            }
+        }
+    }
+
+    /// Only public rooms are listed, private ones have to be joined by code
+    fn server_info(&self) -> ServerInfo {
+        let rooms = self.rooms.lock().unwrap();
+        ServerInfo {
+            rooms: rooms
+                .values()
+                .filter(|room| room.public)
+                .map(|room| room.info())
+                .collect(),
+        }
+    }
+
+    /// Knowing the code is enough to join a private room, so it's enough to look at it too
+    fn room_info(&self, code: &str) -> Option<RoomInfo> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(&normalize_room_code(code))
+            .map(|room| room.info())
+    }
+
+    fn join(&mut self, code: &str, public: bool, token: Option<SessionToken>) {
+        let name = normalize_room_code(code);
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.retain(|room_name, room| room_name == PUBLIC_ROOM || !room.is_empty());
+        let room = rooms
+            .entry(name.clone())
+            .or_insert_with(|| {
+                let public = public || name == PUBLIC_ROOM;
+                Arc::new(Room::new(name, public, &self.config))
+            })
+            .clone();
+        let mut server_state = room.state.lock().unwrap();
+        let (mut welcome, token) = match token.and_then(|token| {
+            server_state
+                .reconnect(token)
+                .map(|player_id| (player_id, token))
+        }) {
+            Some((player_id, token)) => (server_state.model.welcome_back(player_id), token),
+            None => {
+                let (welcome, events) = server_state.model.welcome();
+                server_state.add_events(events);
+                let token = SessionToken::new();
+                server_state.sessions.insert(token, welcome.player_id);
+                (welcome, token)
+            }
+        };
+        welcome.token = Some(token);
+        welcome.next_event = server_state.next_event_index;
+        let player_id = welcome.player_id;
+        let mut sender = self.sender.take().unwrap();
+        sender.send(ServerMessage::Welcome(welcome));
+        let connection_id = server_state.add_client(player_id, sender);
+        drop(server_state);
+        self.session = Some(Session {
+            room,
+            player_id,
+            connection_id,
+            last_update: std::time::Instant::now(),
+        });
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        let mut server_state = self.server_state.lock().unwrap();
-        let events = server_state.model.drop_player(self.player_id);
-        server_state.add_events(events);
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return,
+        };
+        // The player stays in the room for a while in case the client reconnects
+        session
+            .room
+            .state
+            .lock()
+            .unwrap()
+            .disconnect(session.player_id, session.connection_id);
     }
 }
 
 impl geng::net::Receiver<ClientMessage> for Client {
     fn handle(&mut self, message: ClientMessage) {
-        let send_update = matches!(message, ClientMessage::Event(Event::PlayerUpdated(_)));
-        let mut server_state = self.server_state.lock().unwrap();
-        let events = server_state.model.handle_message(
-            self.player_id,
-            message,
-            // &mut *self.sender
-        );
-        server_state.add_events(events);
-        if send_update {
-            self.sender.send(ServerMessage::Update(
-                server_state.get_new_events(self.player_id),
-            ));
+        if self.kicked {
+            return;
+        }
+        let too_big =
+            bincode::serialized_size(&message).map_or(true, |size| size > MAX_MESSAGE_SIZE);
+        if too_big || !self.rate_limiter.allow() {
+            if self.rate_limiter.strike() {
+                self.kick("Too many messages");
+            }
+            return;
+        }
+        if let ClientMessage::Hello(version) = message {
+            self.compatible = version == PROTOCOL_VERSION;
+            if let Some(sender) = &mut self.sender {
+                sender.send(ServerMessage::Hello(PROTOCOL_VERSION));
+            }
+            return;
+        }
+        if !self.compatible {
+            return;
+        }
+        match &message {
+            ClientMessage::QueryServerInfo => {
+                let info = self.server_info();
+                if let Some(sender) = &mut self.sender {
+                    sender.send(ServerMessage::ServerInfo(info));
+                }
+                return;
+            }
+            ClientMessage::QueryRoom(code) => {
+                let info = self.room_info(code);
+                if let Some(sender) = &mut self.sender {
+                    sender.send(ServerMessage::RoomInfo(info));
+                }
+                return;
+            }
+            ClientMessage::Join {
+                room,
+                public,
+                token,
+                name,
+            } => {
+                if self.bans.lock().unwrap().is_banned(name) {
+                    if let Some(sender) = &mut self.sender {
+                        sender.send(ServerMessage::Kicked("You are banned".to_owned()));
+                    }
+                } else if self.session.is_none() {
+                    self.join(room, *public, *token);
+                }
+                return;
+            }
+            ClientMessage::Ping(id) => {
+                if let Some(sender) = &mut self.sender {
+                    sender.send(ServerMessage::Pong(*id));
+                    return;
+                }
+            }
+            _ => {}
+        }
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return,
+        };
+        let room = session.room.clone();
+        let mut server_state = room.state.lock().unwrap();
+        // Timed out, the client has to reconnect to be heard again
+        if !server_state.is_connected(session.player_id, session.connection_id) {
+            return;
         }
+        server_state.seen(session.player_id);
+        if let ClientMessage::Ping(id) = message {
+            if let Some(client) = server_state.clients.get_mut(&session.player_id) {
+                client.sender.send(ServerMessage::Pong(id));
+            }
+            return;
+        }
+        match message {
+            ClientMessage::Leave => {
+                server_state.remove_player(session.player_id, LeaveReason::Quit);
+                return;
+            }
+            ClientMessage::Ack(next_event) => {
+                server_state.ack(session.player_id, next_event);
+                return;
+            }
+            ClientMessage::Resend(from) => {
+                server_state.resend(session.player_id, from);
+                return;
+            }
+            ClientMessage::RequestSnapshot => {
+                if let Some(client) = server_state.clients.get_mut(&session.player_id) {
+                    client.snapshot_requested = true;
+                }
+                return;
+            }
+            _ => {}
+        }
+        let message = match session.validate(&server_state.model, message) {
+            Some(message) => message,
+            None => return,
+        };
+        if let ClientMessage::Event(Event::PlayerUpdated(player)) = &message {
+            if self.bans.lock().unwrap().is_banned(&player.name) {
+                server_state.kick(session.player_id, "You are banned");
+                return;
+            }
+        }
+        let events = server_state
+            .model
+            .handle_message(session.player_id, message);
+        server_state.add_events(events);
     }
 }
+
 struct ServerApp {
-    server_state: Arc<Mutex<ServerState>>,
+    rooms: Rooms,
+    bans: Bans,
+    config: Arc<ServerConfig>,
 }
+
 impl geng::net::server::App for ServerApp {
     type Client = Client;
     type ServerMessage = ServerMessage;
     type ClientMessage = ClientMessage;
-    fn connect(&mut self, mut sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Client {
-        let mut server_state = self.server_state.lock().unwrap();
-        let (welcome, events) = server_state.model.welcome();
-        server_state.add_events(events);
-        let player_id = welcome.player_id;
-        sender.send(ServerMessage::Welcome(welcome));
-        let next_event_index = server_state.next_event_index;
-        server_state
-            .clients_next_event
-            .insert(player_id, next_event_index);
-        sender.send(ServerMessage::Update(vec![]));
+    fn connect(&mut self, sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Client {
         Client {
-            server_state: self.server_state.clone(),
-            player_id,
-            sender,
+            rooms: self.rooms.clone(),
+            bans: self.bans.clone(),
+            config: self.config.clone(),
+            sender: Some(sender),
+            session: None,
+            compatible: false,
+            rate_limiter: RateLimiter::new(),
+            kicked: false,
         }
     }
 }
 
 pub struct Server {
-    server_state: Arc<Mutex<ServerState>>,
+    rooms: Rooms,
+    bans: Bans,
     server: geng::net::Server<ServerApp>,
 }
 
 impl Server {
-    pub fn new<T: std::net::ToSocketAddrs + Debug + Copy>(addr: T, model: Model) -> Self {
-        let server_state = Arc::new(Mutex::new(ServerState::new(model)));
+    pub fn new(config: ServerConfig) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            PUBLIC_ROOM.to_owned(),
+            Arc::new(Room::new(PUBLIC_ROOM.to_owned(), true, &config)),
+        );
+        let rooms = Arc::new(Mutex::new(rooms));
+        let bans = Arc::new(Mutex::new(BanList::load(config.ban_list.clone())));
+        let addr = match &config.tls {
+            Some(tls) => {
+                let proxy = tls::Proxy::bind(tls, &config.addr)
+                    .unwrap_or_else(|e| panic!("Failed to set up TLS on {}: {}", config.addr, e));
+                let addr = proxy.backend_addr();
+                proxy.spawn();
+                addr
+            }
+            None => config.addr.clone(),
+        };
         Self {
-            server_state: server_state.clone(),
+            rooms: rooms.clone(),
+            bans: bans.clone(),
             server: geng::net::Server::new(
                 ServerApp {
-                    server_state: server_state.clone(),
+                    rooms,
+                    bans,
+                    config: Arc::new(config),
                 },
-                addr,
+                &*addr,
             ),
         }
     }
+    /// Reads admin commands from stdin for as long as the process lives
+    pub fn spawn_console(&self) {
+        let console = admin::Console::new(self.rooms.clone(), self.bans.clone());
+        std::thread::spawn(move || console.run());
+    }
     pub fn handle(&self) -> geng::net::ServerHandle {
         self.server.handle()
     }
     pub fn run(self) {
-        let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
-        let server_thread = std::thread::spawn({
-            let server_state = self.server_state;
-            let running = running.clone();
-            let mut sleep_time = 0;
-            move || {
-                while running.load(std::sync::atomic::Ordering::Relaxed) {
-                    // TODO: smoother TPS
-                    std::thread::sleep(std::time::Duration::from_millis(sleep_time));
-                    let mut server_state = server_state.lock().unwrap();
-                    let events = server_state.model.tick();
-                    server_state.add_events(events);
-                    sleep_time = (1000.0 / server_state.model.ticks_per_second) as u64;
-                }
-            }
-        });
         self.server.run();
-        running.store(false, std::sync::atomic::Ordering::Relaxed);
-        server_thread.join().expect("Failed to join server thread");
+        self.rooms.lock().unwrap().clear();
     }
 }
 
@@ -179,3 +906,288 @@ impl Server {
 //             .expect("Failed to join server thread");
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps whatever the server sends so the test can look at it
+    #[derive(Clone, Default)]
+    struct TestSender(Arc<Mutex<Vec<ServerMessage>>>);
+
+    impl geng::net::Sender<ServerMessage> for TestSender {
+        fn send(&mut self, message: ServerMessage) {
+            self.0.lock().unwrap().push(message);
+        }
+    }
+
+    impl TestSender {
+        fn take(&self) -> Vec<ServerMessage> {
+            mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    fn state_with_client() -> (ServerState, Id, TestSender) {
+        let mut state = ServerState::new(Model::new(default()), 0);
+        let (player_id, events) = state.model.spawn_player(false);
+        state.add_events(events);
+        let sender = TestSender::default();
+        state.add_client(player_id, Box::new(sender.clone()));
+        (state, player_id, sender)
+    }
+
+    /// The `(first, number of events)` of every update sent
+    fn updates(sender: &TestSender) -> Vec<(usize, usize)> {
+        sender
+            .take()
+            .into_iter()
+            .map(|message| match message {
+                ServerMessage::Update { first, events } => (first, events.len()),
+                message => panic!("Expected an update, got {:?}", message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn updates_continue_where_the_last_ended() {
+        let (mut state, _, sender) = state_with_client();
+        state.add_events(vec![Event::Reset; 2]);
+        state.send_updates();
+        state.add_events(vec![Event::Reset; 3]);
+        state.send_updates();
+        state.send_updates();
+        assert_eq!(updates(&sender), vec![(1, 2), (3, 3), (6, 0)]);
+    }
+
+    #[test]
+    fn backlog_is_kept_until_acked() {
+        let (mut state, player_id, sender) = state_with_client();
+        state.add_events(vec![Event::Reset; 3]);
+        state.send_updates();
+        assert_eq!(state.first_event_index, 1);
+        assert_eq!(state.events.len(), 3);
+
+        // Events that were never sent can't be confirmed
+        state.ack(player_id, 100);
+        assert_eq!(state.clients[&player_id].acked_event, 4);
+        state.ack(player_id, 2);
+        assert_eq!(state.clients[&player_id].acked_event, 4);
+        state.send_updates();
+        assert_eq!(state.first_event_index, 4);
+        assert!(state.events.is_empty());
+        assert_eq!(updates(&sender), vec![(1, 3), (4, 0)]);
+    }
+
+    #[test]
+    fn resend_repeats_events_after_the_gap() {
+        let (mut state, player_id, sender) = state_with_client();
+        state.add_events(vec![Event::Reset; 3]);
+        state.send_updates();
+        state.resend(player_id, 2);
+        state.add_events(vec![Event::Reset; 1]);
+        state.send_updates();
+        assert_eq!(updates(&sender), vec![(1, 3), (2, 3)]);
+        // Everything before the gap counts as applied
+        assert_eq!(state.first_event_index, 2);
+    }
+
+    #[test]
+    fn reconnect_takes_over_a_live_connection() {
+        let (mut state, player_id, _) = state_with_client();
+        let old_connection = state.clients[&player_id].id;
+        let token = SessionToken::new();
+        state.sessions.insert(token, player_id);
+        assert_eq!(state.reconnect(SessionToken::new()), None);
+        assert_eq!(state.reconnect(token), Some(player_id));
+        let new_connection = state.add_client(player_id, Box::new(TestSender::default()));
+        assert!(!state.is_connected(player_id, old_connection));
+
+        // The old connection going away later doesn't take the player with it
+        state.disconnect(player_id, old_connection);
+        assert!(state.is_connected(player_id, new_connection));
+        assert!(state.disconnected.is_empty());
+        assert!(state.model.players.contains_key(&player_id));
+    }
+
+    #[test]
+    fn busy_room_keeps_events_for_clients_that_keep_up() {
+        let (mut state, _, sender) = state_with_client();
+        state.add_events(vec![Event::Reset; 5000]);
+        state.send_updates();
+        assert_eq!(updates(&sender), vec![(1, 5000)]);
+    }
+
+    #[test]
+    fn stalled_client_gets_one_snapshot() {
+        let (mut state, player_id, sender) = state_with_client();
+        state.add_events(vec![Event::Reset; 3]);
+        for (time, _) in &mut state.events {
+            *time -= ServerState::BACKLOG_TIME * 2;
+        }
+        state.shrink();
+        state.send_updates();
+        let snapshot_event = state.next_event_index;
+        match &sender.take()[..] {
+            [ServerMessage::Snapshot { next_event, .. }] => assert_eq!(*next_event, snapshot_event),
+            messages => panic!("Expected a snapshot, got {:?}", messages),
+        }
+
+        // Nothing else until the snapshot is confirmed, and nothing piles up meanwhile
+        state.add_events(vec![Event::Reset; 10]);
+        state.send_updates();
+        state.resend(player_id, 0);
+        state.send_updates();
+        assert!(sender.take().is_empty());
+        assert_eq!(state.first_event_index, snapshot_event);
+
+        state.ack(player_id, snapshot_event);
+        state.send_updates();
+        assert_eq!(updates(&sender), vec![(snapshot_event, 10)]);
+    }
+
+    #[test]
+    fn checksum_follows_the_update() {
+        let (mut state, player_id, sender) = state_with_client();
+        state.clients.get_mut(&player_id).unwrap().last_checksum -=
+            ServerState::CHECKSUM_INTERVAL * 2;
+        state.send_updates();
+        let expected = state.model.checksum();
+        match &sender.take()[..] {
+            [ServerMessage::Update { .. }, ServerMessage::Checksum {
+                next_event,
+                checksum,
+            }] => {
+                assert_eq!(*next_event, 1);
+                assert_eq!(*checksum, expected);
+            }
+            messages => panic!("Expected an update and a checksum, got {:?}", messages),
+        }
+        state.send_updates();
+        assert_eq!(updates(&sender), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn rate_limiter_allows_a_burst_then_the_rate() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..RateLimiter::BURST as usize {
+            assert!(limiter.allow());
+        }
+        assert!(!limiter.allow());
+
+        limiter.last_refill -= std::time::Duration::from_secs(1);
+        let mut allowed = 0;
+        while limiter.allow() {
+            allowed += 1;
+        }
+        assert_eq!(allowed, RateLimiter::RATE as usize);
+    }
+
+    #[test]
+    fn rate_limiter_strikes_cool_down() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..RateLimiter::MAX_STRIKES as usize {
+            assert!(!limiter.strike());
+        }
+        limiter.last_refill -= std::time::Duration::from_secs(10);
+        limiter.allow();
+        for _ in 0..10 {
+            assert!(!limiter.strike());
+        }
+        assert!(limiter.strike());
+    }
+
+    #[test]
+    fn player_update_keeps_identity_and_speed() {
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        let (other_id, _) = model.spawn_player(false);
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.id = other_id;
+        player.radius = 100.0;
+        player.name = "  Mallory!!  ".to_owned();
+        player.target_velocity = vec2(100.0, 0.0);
+        player.velocity = vec2(0.0, 100.0);
+        let player = Session::validate_player_update(&model, &previous, player, 0.0).unwrap();
+        assert_eq!(player.id, player_id);
+        assert_eq!(player.radius, previous.radius);
+        assert_eq!(player.name, "Mallory");
+        assert!(player.target_velocity.len() <= 1.0 + 1e-3);
+        assert!(player.velocity.len() <= model.config.player_speed + 1e-3);
+    }
+
+    #[test]
+    fn player_update_cant_teleport() {
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.position = previous.position + vec2(-10.0, 3.0);
+        let player = Session::validate_player_update(&model, &previous, player, 0.0).unwrap();
+        assert!((player.position - previous.position).len() <= POSITION_TOLERANCE + 0.1);
+    }
+
+    #[test]
+    fn player_update_cant_change_employment() {
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.unemployed_time = None;
+        let player = Session::validate_player_update(&model, &previous, player, 0.0).unwrap();
+        assert!(player.unemployed_time.is_some());
+
+        model.handle(Event::Hire(player_id));
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.unemployed_time = Some(100.0);
+        let player = Session::validate_player_update(&model, &previous, player, 0.0).unwrap();
+        assert!(player.unemployed_time.is_none());
+    }
+
+    #[test]
+    fn player_update_with_nan_is_dropped() {
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.position.x = f32::NAN;
+        assert!(Session::validate_player_update(&model, &previous, player, 0.0).is_none());
+        let mut player = previous.clone();
+        player.velocity.y = f32::INFINITY;
+        assert!(Session::validate_player_update(&model, &previous, player, 0.0).is_none());
+    }
+
+    #[test]
+    fn only_customers_sit_on_free_seats() {
+        let mut model = Model::new(default());
+        let (player_id, _) = model.spawn_player(false);
+        let seat = model.level.seats[0].clone();
+        model.players.get_mut(&player_id).unwrap().position = seat.position + vec2(0.1, 0.0);
+        let previous = model.players[&player_id].clone();
+        let mut player = previous.clone();
+        player.seat = Some(0);
+        let seated =
+            Session::validate_player_update(&model, &previous, player.clone(), 0.0).unwrap();
+        assert_eq!(seated.seat, Some(0));
+        assert_eq!(seated.position, seat.position);
+
+        let missing = model.level.seats.len();
+        let mut nowhere = player.clone();
+        nowhere.seat = Some(missing);
+        let nowhere = Session::validate_player_update(&model, &previous, nowhere, 0.0).unwrap();
+        assert_eq!(nowhere.seat, None);
+
+        let (other_id, _) = model.spawn_player(false);
+        model.players.get_mut(&other_id).unwrap().seat = Some(0);
+        let taken =
+            Session::validate_player_update(&model, &previous, player.clone(), 0.0).unwrap();
+        assert_eq!(taken.seat, None);
+        model.players.get_mut(&other_id).unwrap().seat = None;
+
+        model.handle(Event::Hire(player_id));
+        let previous = model.players[&player_id].clone();
+        let cook = Session::validate_player_update(&model, &previous, player, 0.0).unwrap();
+        assert_eq!(cook.seat, None);
+    }
+}
@@ -0,0 +1,234 @@
+use super::*;
+
+const HELP: &str = "\
+rooms                  list rooms
+room <name>            select the room other commands act on
+players                list players in the selected room
+kick <player> [reason] remove a player, they may join again
+ban <name>             kick everyone with this name and keep them out,
+                       bans go by name only so they can come back under another one
+unban <name>
+bans                   list banned names
+hire <player>          make a player a cook
+fire <player>          make a player a customer
+reset-timer            restart the boss firing timer
+tick-rate <ticks>      change how many ticks per second the room runs
+say <message>          show a message to everyone in every room
+players are given by id or by name";
+
+/// Admin commands for a dedicated server, read line by line from stdin
+pub struct Console {
+    rooms: Rooms,
+    bans: Bans,
+    room: String,
+}
+
+impl Console {
+    pub fn new(rooms: Rooms, bans: Bans) -> Self {
+        Self {
+            rooms,
+            bans,
+            room: PUBLIC_ROOM.to_owned(),
+        }
+    }
+
+    pub fn run(mut self) {
+        use std::io::BufRead;
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match self.execute(line.trim()) {
+                Ok(output) => {
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                }
+                Err(error) => println!("error: {}", error),
+            }
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+        let (command, args) = match line.find(' ') {
+            Some(index) => (&line[..index], line[index + 1..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(HELP.to_owned()),
+            "rooms" => {
+                let rooms = self.rooms.lock().unwrap();
+                Ok(rooms
+                    .values()
+                    .map(|room| {
+                        let info = room.info();
+                        format!(
+                            "{}: {} players ({} bots), {} cooks, tick lag {:.1}ms",
+                            info.name,
+                            info.players,
+                            info.bots,
+                            info.employees,
+                            info.tick_lag * 1000.0,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            "room" => {
+                let name = normalize_room_code(args);
+                if !self.rooms.lock().unwrap().contains_key(&name) {
+                    return Err(format!("no room {:?}", name));
+                }
+                self.room = name;
+                Ok(format!("selected room {:?}", self.room))
+            }
+            "players" => self.with_room(|state| {
+                let mut players: Vec<&Player> = state.model.players.values().collect();
+                players.sort_by_key(|player| player.id.raw());
+                players
+                    .into_iter()
+                    .map(|player| {
+                        format!(
+                            "{}\t{:?}\t{}\tscore {}{}{}",
+                            player.id.raw(),
+                            player.name,
+                            if player.unemployed_time.is_none() {
+                                "cook"
+                            } else {
+                                "customer"
+                            },
+                            player.score,
+                            if player.bot { "\tbot" } else { "" },
+                            if state.disconnected.contains_key(&player.id) {
+                                "\tdisconnected"
+                            } else {
+                                ""
+                            },
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }),
+            "kick" => self.with_room(|state| {
+                let (player, reason) = match args.find(' ') {
+                    Some(index) => (&args[..index], args[index + 1..].trim()),
+                    None => (args, "Kicked by the admin"),
+                };
+                let player_id = find_player(&state.model, player)?;
+                state.kick(player_id, reason);
+                Ok(format!("kicked {}", player_id.raw()))
+            })?,
+            "ban" => {
+                let name = BanList::key(args);
+                if name.is_empty() {
+                    return Err("ban whom?".to_owned());
+                }
+                self.bans.lock().unwrap().ban(&name);
+                let mut kicked = 0;
+                for room in self.rooms.lock().unwrap().values() {
+                    let mut state = room.state.lock().unwrap();
+                    let banned: Vec<Id> = state
+                        .model
+                        .players
+                        .values()
+                        .filter(|player| BanList::key(&player.name) == name)
+                        .map(|player| player.id)
+                        .collect();
+                    for player_id in banned {
+                        state.kick(player_id, "You are banned");
+                        kicked += 1;
+                    }
+                }
+                Ok(format!("banned {:?}, kicked {} players", name, kicked))
+            }
+            "unban" => {
+                let name = BanList::key(args);
+                if self.bans.lock().unwrap().unban(&name) {
+                    Ok(format!("unbanned {:?}", name))
+                } else {
+                    Err(format!("{:?} is not banned", name))
+                }
+            }
+            "bans" => {
+                let bans = self.bans.lock().unwrap();
+                Ok(bans.names().cloned().collect::<Vec<_>>().join("\n"))
+            }
+            "hire" | "fire" => self.with_room(|state| {
+                let player_id = find_player(&state.model, args)?;
+                let event = if command == "hire" {
+                    Event::Hire(player_id)
+                } else {
+                    Event::Fire(player_id)
+                };
+                state.model.handle(event.clone());
+                state.add_events(vec![event]);
+                Ok(format!("{}d {}", command, player_id.raw()))
+            })?,
+            "reset-timer" => self.with_room(|state| {
+                state.model.boss.timer = 0.0;
+                let boss = state.model.boss.clone();
+                state.add_events(vec![Event::BossUpdate(boss)]);
+                "boss timer reset".to_owned()
+            }),
+            "tick-rate" => {
+                let ticks_per_second: f64 = args
+                    .parse()
+                    .map_err(|_| format!("{:?} is not a number", args))?;
+                self.with_room(|state| {
+                    let mut config = state.model.config.clone();
+                    config.ticks_per_second = ticks_per_second;
+                    config.validate()?;
+                    state.model.config = config.clone();
+                    state.add_events(vec![Event::ConfigUpdated(config)]);
+                    Ok(format!("running at {} ticks per second", ticks_per_second))
+                })?
+            }
+            "say" => {
+                if args.is_empty() {
+                    return Err("say what?".to_owned());
+                }
+                for room in self.rooms.lock().unwrap().values() {
+                    room.state
+                        .lock()
+                        .unwrap()
+                        .add_events(vec![Event::Announcement(args.to_owned())]);
+                }
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command {:?}, try help", command)),
+        }
+    }
+
+    fn with_room<T>(&self, f: impl FnOnce(&mut ServerState) -> T) -> Result<T, String> {
+        let room = self
+            .rooms
+            .lock()
+            .unwrap()
+            .get(&self.room)
+            .cloned()
+            .ok_or_else(|| format!("room {:?} is gone", self.room))?;
+        let mut state = room.state.lock().unwrap();
+        Ok(f(&mut state))
+    }
+}
+
+fn find_player(model: &Model, query: &str) -> Result<Id, String> {
+    if let Ok(raw) = query.parse::<usize>() {
+        if let Some(player) = model.players.values().find(|player| player.id.raw() == raw) {
+            return Ok(player.id);
+        }
+    }
+    let query = query.to_lowercase();
+    let mut matches = model
+        .players
+        .values()
+        .filter(|player| player.name.to_lowercase() == query);
+    match (matches.next(), matches.next()) {
+        (Some(player), None) => Ok(player.id),
+        (Some(_), Some(_)) => Err(format!("more than one player is called {:?}", query)),
+        (None, _) => Err(format!("no player {:?}", query)),
+    }
+}
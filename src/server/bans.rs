@@ -0,0 +1,71 @@
+use super::*;
+
+/// Banned player names, one per line in a text file so it can be edited by hand too.
+///
+/// There are no address bans: geng's server hands [ServerApp](super::ServerApp) a sender
+/// and nothing else, so we never learn where a client connects from.
+/// A banned player can get back in by picking another name
+pub struct BanList {
+    path: Option<std::path::PathBuf>,
+    names: std::collections::BTreeSet<String>,
+}
+
+impl BanList {
+    pub fn load(path: Option<std::path::PathBuf>) -> Self {
+        let names = match &path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => contents
+                    .lines()
+                    .map(Self::key)
+                    .filter(|name| !name.is_empty())
+                    .collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => default(),
+                Err(e) => panic!("Failed to read ban list {:?}: {}", path, e),
+            },
+            None => default(),
+        };
+        Self { path, names }
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut contents = String::new();
+        for name in &self.names {
+            contents.push_str(name);
+            contents.push('\n');
+        }
+        if let Err(e) = std::fs::write(path, contents) {
+            error!("Failed to save ban list {:?}: {}", path, e);
+        }
+    }
+
+    /// How a name is stored and compared: the way the server normalizes player names, any case
+    pub fn key(name: &str) -> String {
+        normalize_name(name).to_lowercase()
+    }
+
+    pub fn is_banned(&self, name: &str) -> bool {
+        self.names.contains(&Self::key(name))
+    }
+
+    pub fn ban(&mut self, name: &str) {
+        if self.names.insert(Self::key(name)) {
+            self.save();
+        }
+    }
+
+    pub fn unban(&mut self, name: &str) -> bool {
+        let removed = self.names.remove(&Self::key(name));
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.names.iter()
+    }
+}
@@ -0,0 +1,185 @@
+use super::*;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+
+type TlsConnection = Arc<Mutex<rustls::ServerConnection>>;
+
+/// Accepts `wss://` connections on the configured address and passes the decrypted
+/// stream on to geng's plain websocket server, which only listens on loopback
+pub struct Proxy {
+    listener: TcpListener,
+    backend: SocketAddr,
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl Proxy {
+    pub fn bind(tls: &TlsConfig, addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let certs: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut std::io::BufReader::new(
+            std::fs::File::open(&tls.cert)?,
+        ))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+        if certs.is_empty() {
+            return Err(format!("No certificates in {:?}", tls.cert).into());
+        }
+        let key =
+            rustls_pemfile::read_all(&mut std::io::BufReader::new(std::fs::File::open(&tls.key)?))?
+                .into_iter()
+                .find_map(|item| match item {
+                    rustls_pemfile::Item::RSAKey(key)
+                    | rustls_pemfile::Item::PKCS8Key(key)
+                    | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+                    _ => None,
+                })
+                .ok_or_else(|| format!("No private key in {:?}", tls.key))?;
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        // Let the OS pick a free port for the backend, geng binds it again right after
+        let backend = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            backend,
+            config: Arc::new(config),
+        })
+    }
+    /// Where geng's server has to listen for the proxy to reach it
+    pub fn backend_addr(&self) -> String {
+        self.backend.to_string()
+    }
+    pub fn spawn(self) {
+        std::thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Failed to accept a TLS connection: {}", e);
+                        continue;
+                    }
+                };
+                let config = self.config.clone();
+                let backend = self.backend;
+                std::thread::spawn(move || {
+                    // Dropped connections are business as usual, only TLS failures are reported
+                    if let Err(e) = forward(config, stream, backend) {
+                        if e.kind() == std::io::ErrorKind::InvalidData {
+                            warn!("TLS connection failed: {}", e);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn io_error(e: rustls::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// Runs one connection, decrypting on a second thread and encrypting on this one
+fn forward(
+    config: Arc<rustls::ServerConfig>,
+    mut client: TcpStream,
+    backend: SocketAddr,
+) -> std::io::Result<()> {
+    let tls = Arc::new(Mutex::new(
+        rustls::ServerConnection::new(config).map_err(io_error)?,
+    ));
+    let mut backend = TcpStream::connect(backend)?;
+    let decrypt_thread = {
+        let tls = tls.clone();
+        let mut client = client.try_clone()?;
+        let mut backend = backend.try_clone()?;
+        std::thread::spawn(move || {
+            let result = decrypt(&tls, &mut client, &mut backend);
+            // The other direction sees the backend go away and says goodbye to the client
+            backend.shutdown(Shutdown::Both).ok();
+            result
+        })
+    };
+    let result = encrypt(&tls, &mut backend, &mut client);
+    close(&client, &backend);
+    let decrypt_result = decrypt_thread.join().unwrap();
+    result.and(decrypt_result)
+}
+
+/// Wakes up whichever direction is still blocked reading
+fn close(client: &TcpStream, backend: &TcpStream) {
+    client.shutdown(Shutdown::Both).ok();
+    backend.shutdown(Shutdown::Both).ok();
+}
+
+/// Everything written to the client goes through here or [decrypt] with the lock held,
+/// so handshake messages and application data never interleave
+fn flush(tls: &mut rustls::ServerConnection, client: &mut TcpStream) -> std::io::Result<()> {
+    while tls.wants_write() {
+        tls.write_tls(client)?;
+    }
+    Ok(())
+}
+
+fn decrypt(
+    tls: &TlsConnection,
+    client: &mut TcpStream,
+    backend: &mut TcpStream,
+) -> std::io::Result<()> {
+    let mut buffer = [0; 16 * 1024];
+    loop {
+        // Reading is done without the lock, the other direction has to keep going meanwhile
+        let len = client.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let mut plaintext = Vec::new();
+        let closed = {
+            let mut tls = tls.lock().unwrap();
+            let mut data = &buffer[..len];
+            let mut closed = false;
+            while !data.is_empty() {
+                tls.read_tls(&mut data)?;
+                let state = match tls.process_new_packets() {
+                    Ok(state) => state,
+                    Err(e) => {
+                        // Lets the client know what went wrong before hanging up
+                        flush(&mut tls, client).ok();
+                        return Err(io_error(e));
+                    }
+                };
+                if state.plaintext_bytes_to_read() > 0 {
+                    let start = plaintext.len();
+                    plaintext.resize(start + state.plaintext_bytes_to_read(), 0);
+                    tls.reader().read_exact(&mut plaintext[start..])?;
+                }
+                closed |= state.peer_has_closed();
+            }
+            flush(&mut tls, client)?;
+            closed
+        };
+        backend.write_all(&plaintext)?;
+        if closed {
+            return Ok(());
+        }
+    }
+}
+
+fn encrypt(
+    tls: &TlsConnection,
+    backend: &mut TcpStream,
+    client: &mut TcpStream,
+) -> std::io::Result<()> {
+    let mut buffer = [0; 16 * 1024];
+    loop {
+        let len = backend.read(&mut buffer)?;
+        let mut tls = tls.lock().unwrap();
+        if len == 0 {
+            tls.send_close_notify();
+            flush(&mut tls, client).ok();
+            return Ok(());
+        }
+        tls.writer().write_all(&buffer[..len])?;
+        flush(&mut tls, client)?;
+    }
+}